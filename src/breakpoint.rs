@@ -0,0 +1,139 @@
+use std::fmt;
+
+use crate::{SandGrid, NONE};
+
+/// A condition on the grid worth stopping to look at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Breakpoint {
+    /// More than this many particles moved in the last update.
+    ActiveAbove(usize),
+    /// Something moved into or through the cell at `(x, y)`.
+    CellActive(usize, usize),
+    /// A grain is stuck in mid-air (see `SandGrid::find_floating_grains`).
+    Floating,
+}
+
+impl Breakpoint {
+    /// Read a condition written as `active>N`, `cell=X,Y` or `floating`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if text.eq_ignore_ascii_case("floating") {
+            Some(Breakpoint::Floating)
+        } else if let Some(n) = text.strip_prefix("active>") {
+            n.trim().parse().ok().map(Breakpoint::ActiveAbove)
+        } else if let Some(cell) = text.strip_prefix("cell=") {
+            let (x, y) = cell.split_once(',')?;
+            Some(Breakpoint::CellActive(x.trim().parse().ok()?, y.trim().parse().ok()?))
+        } else {
+            None
+        }
+    }
+
+    pub fn is_met(&self, grid: &SandGrid) -> bool {
+        match *self {
+            Breakpoint::ActiveAbove(n) => grid.active_count() > n,
+            Breakpoint::CellActive(x, y) => grid
+                .get(x as isize, y as isize)
+                .is_some_and(|p| p.p_type() != NONE && p.is_active()),
+            Breakpoint::Floating => !grid.find_floating_grains().is_empty(),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakpoint::ActiveAbove(n) => write!(f, "active>{}", n),
+            Breakpoint::CellActive(x, y) => write!(f, "cell={},{}", x, y),
+            Breakpoint::Floating => write!(f, "floating"),
+        }
+    }
+}
+
+/// Breakpoints to check after each update. Each one fires when its
+/// condition starts to hold, not for as long as it does, so resuming
+/// carries on until the next time.
+#[derive(Clone, Debug, Default)]
+pub struct Breakpoints {
+    // Each breakpoint, and whether it held last check.
+    breakpoints: Vec<(Breakpoint, bool)>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, breakpoint: Breakpoint) {
+        if !self.contains(breakpoint) {
+            self.breakpoints.push((breakpoint, false));
+        }
+    }
+
+    pub fn remove(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.retain(|&(b, _)| b != breakpoint);
+    }
+
+    pub fn contains(&self, breakpoint: Breakpoint) -> bool {
+        self.breakpoints.iter().any(|&(b, _)| b == breakpoint)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Breakpoint> + '_ {
+        self.breakpoints.iter().map(|&(b, _)| b)
+    }
+
+    /// Check every breakpoint against `grid`, returning the first that
+    /// fired.
+    pub fn check(&mut self, grid: &SandGrid) -> Option<Breakpoint> {
+        let mut fired = None;
+        for (breakpoint, held) in self.breakpoints.iter_mut() {
+            let met = breakpoint.is_met(grid);
+            if met && !*held && fired.is_none() {
+                fired = Some(*breakpoint);
+            }
+            *held = met;
+        }
+        fired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Particle, HOLE, MAGNET, SAND};
+
+    #[test]
+    fn active_above_ignores_what_cant_move() {
+        let mut grid = SandGrid::new_empty(10, 10);
+        grid.reseed((1, 2));
+        for x in 0..10 {
+            grid.set(x, 9, Particle::new(HOLE, true));
+        }
+        grid.set(4, 4, Particle::new(MAGNET, true));
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.add(Breakpoint::ActiveAbove(0));
+        assert_eq!(breakpoints.check(&grid), None);
+        grid.update();
+        assert_eq!(breakpoints.check(&grid), None);
+
+        grid.set(0, 0, Particle::new(SAND, true));
+        grid.update();
+        assert_eq!(breakpoints.check(&grid), Some(Breakpoint::ActiveAbove(0)));
+        // Only fires again once it's stopped holding.
+        grid.update();
+        assert_eq!(breakpoints.check(&grid), None);
+    }
+
+    #[test]
+    fn parses_what_it_displays() {
+        for text in &["active>12", "cell=3,4", "floating"] {
+            let breakpoint = Breakpoint::parse(text).unwrap();
+            assert_eq!(breakpoint.to_string(), *text);
+        }
+        assert_eq!(Breakpoint::parse("active>"), None);
+    }
+}
@@ -0,0 +1,229 @@
+/// Which part of the grid is on screen: `zoom` screen pixels per cell, with
+/// `(x, y)` the cell at the top-left corner. Zoom 1 shows the whole grid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub zoom: u32,
+    /// Each screen "pixel" above is a `cell_size x cell_size` block of the
+    /// screen buffer, which is that much bigger than the grid.
+    pub cell_size: u32,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            zoom: 1,
+            cell_size: 1,
+        }
+    }
+}
+
+impl Viewport {
+    /// Size in cells of the region shown on a `width x height` grid.
+    pub fn visible_size(&self, width: usize, height: usize) -> (f32, f32) {
+        let zoom = self.zoom.max(1) as f32;
+        (width as f32 / zoom, height as f32 / zoom)
+    }
+
+    pub fn center(&self, width: usize, height: usize) -> (f32, f32) {
+        let (w, h) = self.visible_size(width, height);
+        (self.x + w / 2.0, self.y + h / 2.0)
+    }
+
+    /// `(x, y, width, height)` of the cells at least partly on screen.
+    pub fn visible_cells(&self, width: usize, height: usize) -> (usize, usize, usize, usize) {
+        let (w, h) = self.visible_size(width, height);
+        let (x0, y0) = (self.x.floor().max(0.0) as usize, self.y.floor().max(0.0) as usize);
+        let x1 = ((self.x + w).ceil() as usize).min(width);
+        let y1 = ((self.y + h).ceil() as usize).min(height);
+        (x0, y0, x1.saturating_sub(x0), y1.saturating_sub(y0))
+    }
+
+    /// Move so `(cx, cy)` is in the middle, keeping the view on the grid.
+    pub fn center_on(&mut self, (cx, cy): (f32, f32), width: usize, height: usize) {
+        let (w, h) = self.visible_size(width, height);
+        self.x = cx - w / 2.0;
+        self.y = cy - h / 2.0;
+        self.clamp(width, height);
+    }
+
+    pub fn clamp(&mut self, width: usize, height: usize) {
+        self.zoom = self.zoom.max(1);
+        let (w, h) = self.visible_size(width, height);
+        self.x = self.x.max(0.0).min(width as f32 - w);
+        self.y = self.y.max(0.0).min(height as f32 - h);
+    }
+
+    /// Change zoom while keeping the same cell in the middle of the view.
+    pub fn set_zoom(&mut self, zoom: u32, width: usize, height: usize) {
+        let center = self.center(width, height);
+        self.zoom = zoom.max(1);
+        self.center_on(center, width, height);
+    }
+
+    /// The grid cell under screen pixel `(px, py)`.
+    pub fn screen_to_cell(&self, (px, py): (isize, isize)) -> (isize, isize) {
+        let zoom = (self.zoom.max(1) * self.cell_size.max(1)) as isize;
+        (
+            self.x.floor() as isize + px.div_euclid(zoom),
+            self.y.floor() as isize + py.div_euclid(zoom),
+        )
+    }
+
+    /// Scale the visible part of a full-grid RGBA image (as written by
+    /// `draw`) up into `screen`, which is `cell_size` times the size of the
+    /// grid each way.
+    pub fn blit(&self, full: &[u8], screen: &mut [u8], width: usize, height: usize) {
+        if full.len() != 4 * width * height {
+            log::warn!("blit: source is {} bytes, expected {}", full.len(), 4 * width * height);
+            return;
+        }
+        let cell_size = self.cell_size.max(1) as usize;
+        let zoom = self.zoom.max(1) as usize * cell_size;
+        let screen_width = width * cell_size;
+        let (x0, y0) = (self.x.floor() as usize, self.y.floor() as usize);
+        for (i, pix) in screen.chunks_exact_mut(4).enumerate() {
+            let sx = (x0 + (i % screen_width) / zoom).min(width - 1);
+            let sy = (y0 + (i / screen_width) / zoom).min(height - 1);
+            let j = 4 * (sx + sy * width);
+            pix.copy_from_slice(&full[j..j + 4]);
+        }
+    }
+}
+
+/// Smoothly pans a viewport towards a target, e.g. the center of mass of
+/// whatever is moving.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraFollow {
+    pub enabled: bool,
+    /// Fraction of the remaining distance covered each step (0.0..=1.0).
+    pub easing: f32,
+}
+
+impl Default for CameraFollow {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            easing: 0.1,
+        }
+    }
+}
+
+impl CameraFollow {
+    /// Ease `view` one step towards centering on `target`. Does nothing when
+    /// disabled or when there's nothing to follow.
+    pub fn step(
+        &self,
+        view: &mut Viewport,
+        target: Option<(f32, f32)>,
+        width: usize,
+        height: usize,
+    ) {
+        if let (true, Some((tx, ty))) = (self.enabled, target) {
+            let (cx, cy) = view.center(width, height);
+            let t = self.easing.clamp(0.0, 1.0);
+            view.center_on((cx + (tx - cx) * t, cy + (ty - cy) * t), width, height);
+        }
+    }
+}
+
+/// Which of several side-by-side panes, each `pane_width` pixels wide, the
+/// screen column `x` falls in, and the column within that pane.
+pub fn split_pane(x: usize, pane_width: usize) -> (usize, usize) {
+    let pane_width = pane_width.max(1);
+    (x / pane_width, x % pane_width)
+}
+
+/// Copy an RGBA image `pane_width` pixels wide into pane number `pane` of
+/// `screen`, which is `screen_width` pixels wide and split into panes of
+/// that width from the left. Rows past the bottom of either are skipped.
+pub fn blit_pane(image: &[u8], pane_width: usize, screen: &mut [u8], screen_width: usize, pane: usize) {
+    let x0 = pane * pane_width;
+    if x0 + pane_width > screen_width {
+        log::warn!("blit_pane: pane {} doesn't fit in a {} pixel wide screen", pane, screen_width);
+        return;
+    }
+    for (src, dst) in image
+        .chunks_exact(4 * pane_width)
+        .zip(screen.chunks_exact_mut(4 * screen_width))
+    {
+        dst[4 * x0..4 * (x0 + pane_width)].copy_from_slice(src);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camera_eases_onto_its_target() {
+        let (width, height) = (100, 100);
+        let mut view = Viewport {
+            zoom: 4,
+            ..Viewport::default()
+        };
+        let mut camera = CameraFollow::default();
+        let target = Some((60.0, 40.0));
+        camera.step(&mut view, target, width, height);
+        assert_eq!(view.center(width, height), (12.5, 12.5), "disabled");
+        camera.enabled = true;
+        camera.step(&mut view, None, width, height);
+        assert_eq!(view.center(width, height), (12.5, 12.5), "nothing to follow");
+        let mut last = (12.5, 12.5);
+        for _ in 0..100 {
+            camera.step(&mut view, target, width, height);
+            let (cx, cy) = view.center(width, height);
+            // Always closing in, never past it.
+            assert!(cx > last.0 && cx <= 60.0, "x went {} -> {}", last.0, cx);
+            assert!(cy > last.1 && cy <= 40.0, "y went {} -> {}", last.1, cy);
+            last = (cx, cy);
+        }
+        assert!((last.0 - 60.0).abs() < 0.01 && (last.1 - 40.0).abs() < 0.01, "ended at {:?}", last);
+    }
+
+    #[test]
+    fn cell_size_blits_blocks() {
+        let (width, height) = (3, 2);
+        let full: Vec<u8> = (0..(width * height) as u8).flat_map(|c| vec![c, c, c, 0xff]).collect();
+        let view = Viewport {
+            cell_size: 2,
+            ..Viewport::default()
+        };
+        let mut screen = vec![0; 4 * width * height * 4];
+        view.blit(&full, &mut screen, width, height);
+        for y in 0..2 * height {
+            for x in 0..2 * width {
+                let cell = (x / 2 + y / 2 * width) as u8;
+                let i = 4 * (x + y * 2 * width);
+                assert_eq!(screen[i..i + 4], [cell, cell, cell, 0xff], "pixel ({}, {})", x, y);
+            }
+        }
+        assert_eq!(view.screen_to_cell((5, 3)), (2, 1));
+    }
+
+    #[test]
+    fn panes_sit_side_by_side() {
+        assert_eq!(split_pane(0, 4), (0, 0));
+        assert_eq!(split_pane(3, 4), (0, 3));
+        assert_eq!(split_pane(4, 4), (1, 0));
+        assert_eq!(split_pane(7, 4), (1, 3));
+
+        let (pane_width, screen_width, height) = (2, 4, 2);
+        let left = vec![1; 4 * pane_width * height];
+        let right = vec![2; 4 * pane_width * height];
+        let mut screen = vec![0; 4 * screen_width * height];
+        blit_pane(&left, pane_width, &mut screen, screen_width, 0);
+        blit_pane(&right, pane_width, &mut screen, screen_width, 1);
+        for (i, pix) in screen.chunks(4).enumerate() {
+            let x = i % screen_width;
+            let expected = if split_pane(x, pane_width).0 == 0 { 1 } else { 2 };
+            assert_eq!(pix, &[expected; 4], "pixel {}", i);
+        }
+        // A pane that doesn't fit is left out.
+        blit_pane(&left, pane_width, &mut screen, screen_width, 2);
+        assert_eq!(screen[4 * 3..4 * 4], [2; 4]);
+    }
+}
@@ -0,0 +1,127 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use crate::{PixelFormat, SandGrid};
+
+/// What size a screenshot is written at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScreenshotScale {
+    /// One pixel per cell, exactly `width x height` of the grid.
+    Native,
+    /// Scaled to the given size (e.g. the window's, or a thumbnail): area
+    /// averaged when shrinking, nearest-neighbour when growing.
+    Scaled { width: u32, height: u32 },
+}
+
+/// Render the grid into a freshly allocated RGBA buffer at `scale`, returning
+/// `(width, height, pixels)`.
+pub fn screenshot_rgba(grid: &SandGrid, scale: ScreenshotScale) -> (u32, u32, Vec<u8>) {
+    let (gw, gh) = (grid.width() as u32, grid.height() as u32);
+    let mut native = grid.render_to_vec();
+    if grid.pixel_format() == PixelFormat::Bgra {
+        // PNGs are always RGBA
+        for pix in native.chunks_exact_mut(4) {
+            pix.swap(0, 2);
+        }
+    }
+    match scale {
+        ScreenshotScale::Native => (gw, gh, native),
+        ScreenshotScale::Scaled { width, height } => {
+            let (width, height) = (width.max(1), height.max(1));
+            (width, height, resample_rgba(&native, gw, gh, width, height))
+        }
+    }
+}
+
+/// The `[start, end)` source range covered by destination pixel `d`. Always
+/// at least one pixel wide, which makes upscaling nearest-neighbour.
+fn source_span(d: u32, src: u32, dst: u32) -> (usize, usize) {
+    let start = (u64::from(d) * u64::from(src) / u64::from(dst)) as usize;
+    let end = (u64::from(d + 1) * u64::from(src) / u64::from(dst)) as usize;
+    (start, end.max(start + 1))
+}
+
+/// Resize an RGBA image, box-averaging each destination pixel over the source
+/// pixels it covers.
+pub fn resample_rgba(src: &[u8], sw: u32, sh: u32, dw: u32, dh: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(4 * dw as usize * dh as usize);
+    for y in 0..dh {
+        let (y0, y1) = source_span(y, sh, dh);
+        for x in 0..dw {
+            let (x0, x1) = source_span(x, sw, dw);
+            let mut sum = [0_u32; 4];
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let i = 4 * (sx + sy * sw as usize);
+                    for (s, &c) in sum.iter_mut().zip(&src[i..i + 4]) {
+                        *s += u32::from(c);
+                    }
+                }
+            }
+            let n = ((x1 - x0) * (y1 - y0)) as u32;
+            out.extend(sum.iter().map(|&s| ((s + n / 2) / n) as u8));
+        }
+    }
+    out
+}
+
+/// Write the grid to `path` as a PNG.
+pub fn save_screenshot<P: AsRef<Path>>(
+    grid: &SandGrid,
+    path: P,
+    scale: ScreenshotScale,
+) -> io::Result<()> {
+    let (width, height, rgba) = screenshot_rgba(grid, scale);
+    write_png(path, width, height, &rgba)
+}
+
+pub(crate) fn write_png<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    rgba: &[u8],
+) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Particle, SAND};
+
+    #[test]
+    fn native_screenshot_is_grid_sized() {
+        let mut grid = SandGrid::new_empty(30, 20);
+        grid.set(4, 7, Particle::new(SAND, false));
+        let (width, height, rgba) = screenshot_rgba(&grid, ScreenshotScale::Native);
+        assert_eq!((width, height), (30, 20));
+        assert_eq!(rgba.len(), 4 * 30 * 20);
+        // Still RGBA when the grid draws BGRA.
+        grid.set_pixel_format(PixelFormat::Bgra);
+        assert_eq!(screenshot_rgba(&grid, ScreenshotScale::Native).2, rgba);
+    }
+
+    #[test]
+    fn halving_averages_each_2x2_block() {
+        let (sw, sh) = (4, 4);
+        let src: Vec<u8> = (0..4 * sw * sh).map(|i| (i * 7 % 256) as u8).collect();
+        let out = resample_rgba(&src, sw, sh, 2, 2);
+        assert_eq!(out.len(), 4 * 2 * 2);
+        for y in 0..2 {
+            for x in 0..2 {
+                for c in 0..4 {
+                    let at = |sx: u32, sy: u32| u32::from(src[(4 * (sx + sy * sw) + c) as usize]);
+                    let sum = at(2 * x, 2 * y) + at(2 * x + 1, 2 * y) + at(2 * x, 2 * y + 1) + at(2 * x + 1, 2 * y + 1);
+                    assert_eq!(u32::from(out[(4 * (x + y * 2) + c) as usize]), (sum + 2) / 4);
+                }
+            }
+        }
+    }
+}
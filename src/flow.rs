@@ -0,0 +1,62 @@
+use std::collections::VecDeque;
+
+/// Rolling grains-per-second estimate built from the per-update crossing
+/// counts reported by `SandGrid::flow_crossings`.
+///
+/// Timestamps are plain seconds supplied by the caller, so this doesn't care
+/// whether they came from a wall clock or a fixed timestep.
+#[derive(Clone, Debug)]
+pub struct FlowRate {
+    window: f64,
+    samples: VecDeque<(f64, u32)>,
+}
+
+impl FlowRate {
+    pub fn new(window: f64) -> Self {
+        assert!(window > 0.0);
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Record `count` crossings at time `now`, forgetting anything older than
+    /// the window.
+    pub fn record(&mut self, now: f64, count: u32) {
+        self.samples.push_back((now, count));
+        while let Some(&(t, _)) = self.samples.front() {
+            if now - t > self.window {
+                self.samples.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Crossings per second over the window.
+    pub fn per_second(&self) -> f64 {
+        let total: u32 = self.samples.iter().map(|&(_, c)| c).sum();
+        f64::from(total) / self.window
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_covers_only_the_window() {
+        let mut rate = FlowRate::new(1.0);
+        rate.record(0.0, 4);
+        rate.record(0.5, 2);
+        assert_eq!(rate.per_second(), 6.0);
+        rate.record(1.25, 1);
+        assert_eq!(rate.per_second(), 3.0);
+        rate.reset();
+        assert_eq!(rate.per_second(), 0.0);
+    }
+}
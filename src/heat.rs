@@ -0,0 +1,63 @@
+/// Most the heat of a cell may rise per update while it's ramping in after
+/// a `randomize`.
+pub const HEAT_RAMP_STEP: f32 = 0.25;
+
+/// How quickly the heat trail left behind moving grains cools off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeatDecay {
+    /// Multiply heat by this every `update`. Trails fade faster the more
+    /// updates run per second, but the result only depends on the update
+    /// count, which is what headless runs and tests want.
+    PerUpdate(f32),
+    /// Halve heat every this many seconds, going by the `dt` passed to
+    /// `SandGrid::cool`, so trails last as long whatever the step rate.
+    HalfLife(f32),
+}
+
+impl Default for HeatDecay {
+    fn default() -> Self {
+        HeatDecay::HalfLife(0.25)
+    }
+}
+
+impl HeatDecay {
+    /// What heat gets multiplied by after `dt` seconds. Per-update decay
+    /// doesn't depend on time at all, so that's always 1.0.
+    pub fn factor(self, dt: f32) -> f32 {
+        match self {
+            HeatDecay::PerUpdate(_) => 1.0,
+            HeatDecay::HalfLife(half_life) if half_life <= 0.0 => 0.0,
+            HeatDecay::HalfLife(half_life) => 0.5_f32.powf(dt.max(0.0) / half_life),
+        }
+    }
+
+    /// What heat gets multiplied by after one `update`.
+    pub fn update_factor(self) -> f32 {
+        match self {
+            HeatDecay::PerUpdate(factor) => factor.clamp(0.0, 1.0),
+            HeatDecay::HalfLife(_) => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decay_depends_only_on_elapsed_time() {
+        let decay = HeatDecay::HalfLife(0.25);
+        assert!((decay.factor(0.25) - 0.5).abs() < 1e-6);
+        let once = decay.factor(0.6);
+        let in_steps = (0..12).map(|_| decay.factor(0.05)).product::<f32>();
+        assert!((once - in_steps).abs() < 1e-5, "{} vs {}", once, in_steps);
+        assert_eq!(decay.update_factor(), 1.0);
+    }
+
+    #[test]
+    fn per_update_decay_ignores_time() {
+        let decay = HeatDecay::PerUpdate(0.9);
+        assert_eq!(decay.factor(10.0), 1.0);
+        assert_eq!(decay.update_factor(), 0.9);
+    }
+}
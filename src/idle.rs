@@ -0,0 +1,68 @@
+/// Decides when the front end has been left alone long enough to start
+/// entertaining itself, screensaver style. Times are in seconds from any
+/// fixed starting point.
+#[derive(Clone, Debug)]
+pub struct IdleTimer {
+    /// Seconds without input before counting as idle.
+    pub idle_after: f64,
+    /// Seconds between screensaver actions once idle.
+    pub period: f64,
+    last_input: f64,
+    last_fired: Option<f64>,
+}
+
+impl IdleTimer {
+    pub fn new(idle_after: f64, period: f64) -> Self {
+        Self {
+            idle_after,
+            period,
+            last_input: 0.0,
+            last_fired: None,
+        }
+    }
+
+    /// Note some user input at `now`, ending any idle spell.
+    pub fn input(&mut self, now: f64) {
+        self.last_input = now;
+        self.last_fired = None;
+    }
+
+    pub fn is_idle(&self, now: f64) -> bool {
+        now - self.last_input >= self.idle_after
+    }
+
+    /// Whether to do a screensaver action at `now`: as soon as we go idle,
+    /// then every `period` seconds for as long as it lasts.
+    pub fn poll(&mut self, now: f64) -> bool {
+        let due = self.is_idle(now) && self.last_fired.map_or(true, |t| now - t >= self.period);
+        if due {
+            self.last_fired = Some(now);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fires_once_idle_then_every_period() {
+        let mut timer = IdleTimer::new(30.0, 5.0);
+        assert!(!timer.poll(29.0));
+        assert!(timer.poll(30.0));
+        assert!(!timer.poll(32.0));
+        assert!(timer.poll(35.0));
+        assert!(timer.poll(41.0));
+    }
+
+    #[test]
+    fn input_starts_the_wait_over() {
+        let mut timer = IdleTimer::new(30.0, 5.0);
+        assert!(timer.poll(30.0));
+        timer.input(31.0);
+        assert!(!timer.is_idle(31.0));
+        assert!(!timer.poll(60.0));
+        assert!(timer.poll(61.0));
+    }
+}
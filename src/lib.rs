@@ -1,45 +1,847 @@
-use log::{debug, error, info, warn};
-use pixels::{Error, Pixels, SurfaceTexture};
-use rand::Rng;
-use winit::dpi::{LogicalPosition, LogicalSize, PhysicalSize};
-use winit::event::{Event, VirtualKeyCode};
-use winit::event_loop::{ControlFlow, EventLoop};
-use winit_input_helper::WinitInputHelper;
-use env_logger::Env;
+use std::collections::HashMap;
 
-const PARTICLETYPES: [&str; 3] = ["NONE", "SAND", "WATER"];
+/// Time the rest of the enclosing block as a `tracing` span called `$name`.
+/// Compiles to nothing unless the `profiling` feature is on.
+macro_rules! profile {
+    ($name:expr) => {
+        #[cfg(feature = "profiling")]
+        let _span = tracing::info_span!($name).entered();
+    };
+}
+
+mod breakpoint;
+mod camera;
+mod export;
+mod flow;
+mod heat;
+mod idle;
+mod life;
+mod materials;
+mod palette;
+mod pattern;
+mod reactions;
+mod refill;
+mod rewind;
+mod save;
+mod script;
+mod session;
+mod sound;
+mod stats;
+mod tuning;
+
+pub use breakpoint::{Breakpoint, Breakpoints};
+pub use camera::{blit_pane, split_pane, CameraFollow, Viewport};
+pub use export::{resample_rgba, save_screenshot, screenshot_rgba, ScreenshotScale};
+pub use flow::FlowRate;
+pub use heat::{HeatDecay, HEAT_RAMP_STEP};
+pub use idle::IdleTimer;
+pub use life::{neighbor_color, BitGrid, Rules};
+pub use materials::{Material, MaterialRegistry, Movement, DAMP, MAX_MATERIALS, SMOKE_DISSIPATION};
+pub use palette::Palette;
+pub use pattern::{
+    detect_format, from_ascii, from_cells, from_rle, parse_pattern, ParseError, Pattern, PatternFormat,
+};
+pub use reactions::{Reaction, ReactionTable};
+pub use refill::{AutoRefill, RefillMode};
+pub use rewind::Rewind;
+pub use save::{SAVE_VERSION, SAVE_VERSION_FLAT, SAVE_VERSION_RLE};
+pub use script::{Command, Script};
+pub use session::{advance, Input, Session};
+#[cfg(feature = "sound")]
+pub use sound::ClickPlayer;
+pub use sound::{ImpactDetector, ImpactTrigger};
+pub use stats::{stats_json, StepStats};
+pub use tuning::StepTuner;
+
+/// The operations the front end needs from a simulation, so it can swap
+/// between the sand grid and other backends (like the bit-packed Life grid).
+pub trait Automaton {
+    fn update(&mut self);
+    fn draw(&self, screen: &mut [u8]);
+    fn toggle(&mut self, x: isize, y: isize) -> bool;
+    fn set_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, active: bool);
+    fn clear(&mut self);
+}
+
+pub const PARTICLETYPES: [&str; 7] = ["NONE", "SAND", "WATER", "MAGNET", "DUST", "HOLE", "SMOKE"];
+
+pub const NONE: usize = 0;
+pub const SAND: usize = 1;
+pub const WATER: usize = 2;
+pub const MAGNET: usize = 3;
+pub const DUST: usize = 4;
+/// An open cell: anything that moves into it falls out of the world.
+pub const HOLE: usize = 5;
+/// Rises, drifts about and fades away.
+pub const SMOKE: usize = 6;
+
+/// How a material falls through empty space: up to `fall_speed` cells at a
+/// time, once every `fall_interval` updates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gravity {
+    pub fall_speed: usize,
+    pub fall_interval: u8,
+}
+
+/// Indexed by built-in particle type.
+pub const GRAVITY: [Gravity; 7] = [
+    Gravity { fall_speed: 0, fall_interval: 1 },
+    Gravity { fall_speed: 2, fall_interval: 1 },
+    Gravity { fall_speed: 1, fall_interval: 1 },
+    Gravity { fall_speed: 0, fall_interval: 1 },
+    Gravity { fall_speed: 1, fall_interval: 3 },
+    Gravity { fall_speed: 0, fall_interval: 1 },
+    Gravity { fall_speed: 1, fall_interval: 2 },
+];
+
+/// Look up a built-in particle type by (case-insensitive) name, e.g.
+/// `"sand"`. Custom materials are looked up in a `MaterialRegistry`.
+pub fn material_by_name(name: &str) -> Option<usize> {
+    PARTICLETYPES.iter().position(|t| t.eq_ignore_ascii_case(name))
+}
+
+/// Loose built-in materials that pile up (and that magnets can grab).
+pub fn is_grain(p_type: usize) -> bool {
+    matches!(p_type, SAND | DUST)
+}
+
+/// Gases drift sideways instead of rising about one update in this many.
+pub const GAS_DRIFT_ODDS: u32 = 3;
+
+/// How far (in cells) liquids and gases look sideways for somewhere lower
+/// (higher, for gases) to flow to. With nowhere within reach they come to
+/// rest, so a level surface settles.
+pub const SPREAD_REACH: usize = 8;
+
+/// How far (in cells) a magnet reaches for loose sand.
+pub const MAGNET_RADIUS: usize = 3;
+
+/// Width and height in cells of the chunks the grid is divided into for
+/// skipping settled regions offscreen.
+pub const CHUNK_SIZE: usize = 16;
+
+/// Speed (cells per update) at which the velocity tint is fully "hot".
+pub const MAX_TINT_SPEED: f32 = 3.0;
+const HOT: [u8; 4] = [0xff, 0x60, 0x00, 0xff];
+
+/// Blend `base` towards a hot orange in proportion to `speed`. Resting
+/// particles (speed 0) keep their base color.
+pub fn speed_color(base: [u8; 4], speed: f32) -> [u8; 4] {
+    heat_color(base, speed / MAX_TINT_SPEED)
+}
+
+/// Blend `base` towards the same hot orange by `heat` (0.0..=1.0).
+pub fn heat_color(base: [u8; 4], heat: f32) -> [u8; 4] {
+    blend(base, HOT, heat)
+}
+
+/// How strongly the ghost of the previous frame shows through.
+pub const GHOST_STRENGTH: f32 = 0.35;
+
+/// Color for an empty cell that held something colored `previous` last
+/// update: `base` with a faint `GHOST_STRENGTH` of `previous` mixed in.
+pub fn ghost_color(base: [u8; 4], previous: [u8; 4]) -> [u8; 4] {
+    blend(base, previous, GHOST_STRENGTH)
+}
+
+/// `from` moved `t` (0.0..=1.0) of the way towards `to`.
+fn blend(from: [u8; 4], to: [u8; 4], t: f32) -> [u8; 4] {
+    let t = t.clamp(0.0, 1.0);
+    let mut color = from;
+    for (c, h) in color.iter_mut().zip(to.iter()) {
+        *c = (f32::from(*c) + (f32::from(*h) - f32::from(*c)) * t).round() as u8;
+    }
+    color
+}
+
+/// Updates a rainbow pour takes to go through every hue.
+pub const RAINBOW_PERIOD: u64 = 240;
+
+/// Color of grains poured at `generation` with `set_rainbow_emit` on: fully
+/// saturated, with the hue going once round the wheel every
+/// `RAINBOW_PERIOD` updates.
+pub fn rainbow_color(generation: u64) -> [u8; 4] {
+    let hue = (generation % RAINBOW_PERIOD) as f32 / RAINBOW_PERIOD as f32 * 6.0;
+    let rising = (hue.fract() * 255.0).round() as u8;
+    let falling = 255 - rising;
+    match hue as u32 {
+        0 => [0xff, rising, 0, 0xff],
+        1 => [falling, 0xff, 0, 0xff],
+        2 => [0, 0xff, rising, 0xff],
+        3 => [0, falling, 0xff, 0xff],
+        4 => [rising, 0, 0xff, 0xff],
+        _ => [0xff, 0, falling, 0xff],
+    }
+}
+
+/// Byte order `draw` writes pixels in. `pixels` wants RGBA, but some
+/// backends expect BGRA.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PixelFormat {
+    #[default]
+    Rgba,
+    Bgra,
+}
+
+impl PixelFormat {
+    /// Reorder an `[r, g, b, a]` color into this format's byte order.
+    pub fn encode(self, [r, g, b, a]: [u8; 4]) -> [u8; 4] {
+        match self {
+            PixelFormat::Rgba => [r, g, b, a],
+            PixelFormat::Bgra => [b, g, r, a],
+        }
+    }
+}
+
+/// Color for a cell visited `count` times when the busiest cell saw `max`:
+/// black through blue and red to yellow.
+pub fn accumulation_color(count: u32, max: u32) -> [u8; 4] {
+    if count == 0 || max == 0 {
+        return [0, 0, 0, 0xff];
+    }
+    // log scale, so rarely visited cells still show up next to busy ones
+    let t = (count as f32).ln_1p() / (max as f32).ln_1p();
+    let ramp = |lo: f32, hi: f32| (((t - lo) / (hi - lo)).clamp(0.0, 1.0) * 255.0).round() as u8;
+    let blue = if t < 0.5 { ramp(0.0, 0.25) } else { 255 - ramp(0.5, 0.75) };
+    [ramp(0.25, 0.5), ramp(0.75, 1.0), blue, 0xff]
+}
+
+/// A particle moving from one cell to another during an `update`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Move {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    pub p_type: usize,
+}
+
+/// Callback run after every `update` with the moves it made.
+pub type UpdateHook = Box<dyn FnMut(&[Move])>;
+
+/// Holds the optional update hook. Hooks aren't cloneable, so a cloned grid
+/// (e.g. a session keyframe) simply starts without one.
+#[derive(Default)]
+struct HookSlot(Option<UpdateHook>);
+
+impl Clone for HookSlot {
+    fn clone(&self) -> Self {
+        HookSlot(None)
+    }
+}
+
+/// Fraction of cells `randomize` fills unless told otherwise.
+pub const DEFAULT_FILL_DENSITY: f32 = 2.0 / 3.0;
+
+/// How many updates `randomize` runs after seeding the noise.
+pub const DEFAULT_WARMUP_UPDATES: usize = 3;
+
+/// Most updates `settle` runs before giving up, e.g. on gas that never
+/// stops drifting.
+pub const MAX_SETTLE_UPDATES: usize = 10_000;
 
+#[derive(Clone)]
 pub struct SandGrid {
         particles: Vec<Particle>,
         width: usize,
         height: usize,
         active_type: usize,
-        // Should always be the same size as `cells`. When updating, we read from
-        // `cells` and write to `scratch_cells`, then swap. Otherwise it's not in
-        // use, and `cells` should be updated directly.
+        // Should always be the same size as `particles`. While the ghost
+        // overlay is on, holds the particles as they were before the last
+        // `update`.
         scratch_particles: Vec<Particle>,
+        show_ghost: bool,
+        // Every random choice `update` makes comes from this (or from
+        // `cell_hash`) and is settled with integer math (see `roll_under`),
+        // so a seeded run plays out the same everywhere. Floats are only
+        // multiplied into chances (e.g. pressure times overburden), which
+        // IEEE 754 rounds the same on every platform, and otherwise only
+        // feed drawing: heat, velocity and the tints.
+        rng: randomize::PCG32,
+        // Warm-up updates still owed by an incremental randomize, and how many
+        // it started with (for progress reporting).
+        warmup_remaining: usize,
+        warmup_total: usize,
+        // Row grains are counted crossing (downwards) for the flow overlay.
+        flow_row: Option<usize>,
+        flow_total: u64,
+        flow_crossings: u32,
+        // 0.0..=1.0, how strongly grains cling to neighbours of the same
+        // material instead of sliding off diagonally.
+        cohesion: f32,
+        // Updates run since the grid was last cleared or randomized, and the
+        // seed the per-cell hash mixes it with.
+        generation: u64,
+        base_seed: u64,
+        // Render moving particles hotter the faster they went last update.
+        speed_tint: bool,
+        // Only filled in while a hook is registered.
+        hook: HookSlot,
+        moves: Vec<Move>,
+        pixel_format: PixelFormat,
+        // How many times a particle has moved into or through each cell.
+        // Never decays; only `reset_accumulation` clears it.
+        visits: Vec<u32>,
+        show_accumulation: bool,
+        reactions: ReactionTable,
+        materials: MaterialRegistry,
+        // Grains that fell into a hole, and gas that faded away, during the
+        // last `update`.
+        drained: u32,
+        dissipated: u32,
+        // Trail left behind moving particles: 1.0 where one just left,
+        // cooling towards 0.0 according to `heat_decay`.
+        heat: Vec<f32>,
+        // Color each cell's heat is drawn in: the trail of whatever last
+        // left it.
+        trail: Vec<[u8; 4]>,
+        heat_decay: HeatDecay,
+        show_heat: bool,
+        // Updates left during which heat may only rise `HEAT_RAMP_STEP` per
+        // update, so the mass movement after `randomize` fades in instead of
+        // flashing.
+        heat_ramp: usize,
+        // Chance each cell gets filled by `randomize`.
+        fill_density: f32,
+        // Fewest empty cells `randomize` leaves between particles.
+        min_spacing: usize,
+        // Depth of the border kept empty (see `set_margin`).
+        margin: usize,
+        rainbow_emit: bool,
+        // `(x, y, width, height)` of the cells the next `update` has to look
+        // at: whatever changed or could move by itself last update, and
+        // their neighbours. Zero-sized when everything has settled, `None`
+        // for the whole grid (after an edit, say).
+        awake: Option<(usize, usize, usize, usize)>,
+        // The cells changed so far this update.
+        stirred: (usize, usize, usize, usize),
+        // Cap on how many cells any grain falls in one update.
+        max_fall: usize,
+        // How much each grain piled on top makes a grain less likely to
+        // slide, and the per-cell count of grains above, filled in at the
+        // start of each `update` while pressure is on.
+        pressure: f32,
+        overburden: Vec<u32>,
+        // `(x, y, width, height)` in cells of the part of the grid on screen,
+        // if that's not all of it.
+        visible: Option<(usize, usize, usize, usize)>,
+        offscreen_interval: u64,
     }
     
     impl SandGrid {
         pub fn new_empty(width: usize, height: usize) -> Self {
             assert!(width != 0 && height != 0);
-            let size = width.checked_mul(height).expect("too big");
+            // Neighbours are indices as `isize`s (see `getEightNeighbors`),
+            // so every index has to fit in one.
+            let size = width
+                .checked_mul(height)
+                .filter(|&size| size <= isize::MAX as usize)
+                .expect("too big");
+            let seed = generate_seed();
             Self {
                 particles: vec![Particle::default(); size],
                 scratch_particles: vec![Particle::default(); size],
+                show_ghost: false,
                 active_type: 1,
                 width,
                 height,
+                rng: seed.into(),
+                warmup_remaining: 0,
+                warmup_total: 0,
+                flow_row: None,
+                flow_total: 0,
+                flow_crossings: 0,
+                cohesion: 0.0,
+                generation: 0,
+                base_seed: seed.0 ^ seed.1,
+                speed_tint: false,
+                hook: HookSlot::default(),
+                moves: Vec::new(),
+                pixel_format: PixelFormat::default(),
+                visits: vec![0; size],
+                show_accumulation: false,
+                reactions: ReactionTable::builtin(),
+                materials: MaterialRegistry::builtin(),
+                drained: 0,
+                dissipated: 0,
+                heat: vec![0.0; size],
+                trail: vec![HOT; size],
+                heat_decay: HeatDecay::default(),
+                show_heat: false,
+                heat_ramp: 0,
+                fill_density: DEFAULT_FILL_DENSITY,
+                min_spacing: 0,
+                margin: 0,
+                rainbow_emit: false,
+                awake: None,
+                stirred: (0, 0, 0, 0),
+                max_fall: usize::MAX,
+                pressure: 0.0,
+                overburden: Vec::new(),
+                visible: None,
+                offscreen_interval: 1,
             }
         }
+
+        pub fn width(&self) -> usize {
+            self.width
+        }
+
+        pub fn height(&self) -> usize {
+            self.height
+        }
+
+        /// Set how sticky grains are towards the same material (0.0 never
+        /// resists sliding, 1.0 holds fast when fully surrounded).
+        pub fn set_cohesion(&mut self, cohesion: f32) {
+            self.wake();
+            self.cohesion = cohesion.clamp(0.0, 1.0);
+        }
+
+        pub fn cohesion(&self) -> f32 {
+            self.cohesion
+        }
+
+        /// Limit how far any grain falls in one update, on top of its
+        /// material's `fall_speed`. At least 1; `usize::MAX` (the default)
+        /// leaves gravity alone.
+        pub fn set_max_fall(&mut self, max_fall: usize) {
+            self.wake();
+            self.max_fall = max_fall.max(1);
+        }
+
+        pub fn max_fall(&self) -> usize {
+            self.max_fall
+        }
+
+        /// Set how much the weight of grains above locks a grain in place:
+        /// with `n` grains above it, a grain holds still instead of sliding
+        /// with chance `pressure * n` (capped at 1). 0.0 turns it off.
+        pub fn set_pressure(&mut self, pressure: f32) {
+            self.wake();
+            self.pressure = pressure.max(0.0);
+        }
+
+        pub fn pressure(&self) -> f32 {
+            self.pressure
+        }
+
+        /// Tell the grid which cells `(x, y, width, height)` are on screen,
+        /// or `None` for all of them. See `set_offscreen_interval`.
+        pub fn set_visible_region(&mut self, region: Option<(usize, usize, usize, usize)>) {
+            self.visible = region;
+        }
+
+        pub fn visible_region(&self) -> Option<(usize, usize, usize, usize)> {
+            self.visible
+        }
+
+        /// Only update settled chunks (`CHUNK_SIZE` cells square, with nothing
+        /// moving in or next to them) outside the visible region every
+        /// `interval` updates, rather than every update. Anything moving keeps
+        /// its neighbourhood awake, so grains crossing into view behave as
+        /// usual; an offscreen chunk that's disturbed some other way (an edit,
+        /// a reaction) may take up to `interval` updates to respond. 1, the
+        /// default, updates everything every time.
+        pub fn set_offscreen_interval(&mut self, interval: u64) {
+            self.offscreen_interval = interval.max(1);
+        }
+
+        pub fn offscreen_interval(&self) -> u64 {
+            self.offscreen_interval
+        }
+
+        fn chunk_of(&self, idx: usize) -> usize {
+            let chunks_wide = (self.width + CHUNK_SIZE - 1) / CHUNK_SIZE;
+            let (x, y) = self.getXYfromInx(idx);
+            x / CHUNK_SIZE + (y / CHUNK_SIZE) * chunks_wide
+        }
+
+        /// Which chunks to update this time, or `None` for all of them.
+        fn due_chunks(&self) -> Option<Vec<bool>> {
+            let (vx, vy, vw, vh) = self.visible?;
+            if self.offscreen_interval <= 1 || self.generation % self.offscreen_interval == 0 {
+                return None;
+            }
+            let chunks_wide = (self.width + CHUNK_SIZE - 1) / CHUNK_SIZE;
+            let chunks_high = (self.height + CHUNK_SIZE - 1) / CHUNK_SIZE;
+            let mut moving = vec![false; chunks_wide * chunks_high];
+            for (idx, p) in self.particles.iter().enumerate() {
+                if p.active && self.materials.movement(p.p_type).is_some_and(|m| m != Movement::Solid) {
+                    moving[self.chunk_of(idx)] = true;
+                }
+            }
+            let (left, top) = (vx / CHUNK_SIZE, vy / CHUNK_SIZE);
+            let right = ((vx + vw + CHUNK_SIZE - 1) / CHUNK_SIZE).min(chunks_wide);
+            let bottom = ((vy + vh + CHUNK_SIZE - 1) / CHUNK_SIZE).min(chunks_high);
+            let mut due = vec![false; moving.len()];
+            for cy in 0..chunks_high {
+                for cx in 0..chunks_wide {
+                    let on_screen = (left..right).contains(&cx) && (top..bottom).contains(&cy);
+                    let near_moving = (cy.saturating_sub(1)..(cy + 2).min(chunks_high)).any(|ny| {
+                        (cx.saturating_sub(1)..(cx + 2).min(chunks_wide)).any(|nx| moving[nx + ny * chunks_wide])
+                    });
+                    due[cx + cy * chunks_wide] = on_screen || near_moving;
+                }
+            }
+            Some(due)
+        }
+
+        /// Grains above `(x, y)` in its column, as of the start of the last
+        /// `update`. Always 0 while pressure is off.
+        pub fn overburden(&self, x: isize, y: isize) -> Option<u32> {
+            self.grid_idx(x, y)
+                .map(|i| self.overburden.get(i).copied().unwrap_or(0))
+        }
+
+        /// Count the grains above every cell, one pass per column.
+        fn compute_overburden(&mut self) {
+            if self.pressure <= 0.0 {
+                self.overburden.clear();
+                return;
+            }
+            self.overburden.resize(self.particles.len(), 0);
+            for x in 0..self.width {
+                let mut above = 0;
+                for y in 0..self.height {
+                    let i = x + y * self.width;
+                    self.overburden[i] = above;
+                    if self.materials.is_grain(self.particles[i].p_type) {
+                        above += 1;
+                    }
+                }
+            }
+        }
+
+        /// Roll whether the weight above the grain at `idx` keeps it from
+        /// sliding this update.
+        fn pressed(&mut self, idx: usize) -> bool {
+            if self.pressure <= 0.0 {
+                return false;
+            }
+            let chance = self.pressure * self.overburden[idx] as f32;
+            chance >= 1.0 || self.roll(chance)
+        }
+
+        /// Roll whether the grain at `idx` sticks to its like neighbours
+        /// rather than sliding this update.
+        fn sticks(&mut self, idx: usize, neighbors: &[isize]) -> bool {
+            if self.cohesion <= 0.0 {
+                return false;
+            }
+            let p_type = self.particles[idx].p_type;
+            let alike = neighbors
+                .iter()
+                .filter(|&&n| n > -1 && self.particles[n as usize].p_type == p_type)
+                .count();
+            let chance = self.cohesion * alike as f32 / 8.0;
+            self.roll(chance)
+        }
+
+        /// Draw from the grid's PRNG and see if it comes in under `chance`.
+        fn roll(&mut self, chance: f32) -> bool {
+            roll_under(self.rng.next_u32(), chance)
+        }
+
+        /// Times particles have moved into or through `(x, y)`.
+        pub fn visits(&self, x: isize, y: isize) -> Option<u32> {
+            self.grid_idx(x, y).map(|i| self.visits[i])
+        }
+
+        pub fn reset_accumulation(&mut self) {
+            for v in self.visits.iter_mut() {
+                *v = 0;
+            }
+        }
+
+        /// How hot the trail at `(x, y)` is, 0.0..=1.0.
+        pub fn heat(&self, x: isize, y: isize) -> Option<f32> {
+            self.grid_idx(x, y).map(|i| self.heat[i])
+        }
+
+        pub fn set_heat_decay(&mut self, decay: HeatDecay) {
+            self.heat_decay = decay;
+        }
+
+        pub fn heat_decay(&self) -> HeatDecay {
+            self.heat_decay
+        }
+
+        /// Cool the heat trail by `dt` seconds of real time. Does nothing
+        /// with `HeatDecay::PerUpdate`, which cools in `update` instead.
+        pub fn cool(&mut self, dt: f32) {
+            self.scale_heat(self.heat_decay.factor(dt));
+        }
+
+        fn scale_heat(&mut self, factor: f32) {
+            if factor < 1.0 {
+                for h in self.heat.iter_mut() {
+                    *h *= factor;
+                }
+            }
+        }
+
+        /// How `draw` colors a material, or `None` for ones drawn as background.
+        fn color_of(&self, p_type: usize) -> Option<[u8; 4]> {
+            self.materials.get(p_type).and_then(|m| m.color)
+        }
+
+        pub fn materials(&self) -> &MaterialRegistry {
+            &self.materials
+        }
+
+        /// The material definitions `update` and `draw` use, e.g. to load
+        /// custom materials into. New materials only sink through others
+        /// once `reactions_mut` gets `ReactionTable::for_materials`.
+        pub fn materials_mut(&mut self) -> &mut MaterialRegistry {
+            self.wake();
+            &mut self.materials
+        }
+
+        /// Faintly draw where particles were before the last update, so it's
+        /// clear which way things moved.
+        pub fn set_show_ghost(&mut self, show: bool) {
+            if show && !self.show_ghost {
+                self.scratch_particles.copy_from_slice(&self.particles);
+            }
+            self.show_ghost = show;
+        }
+
+        pub fn show_ghost(&self) -> bool {
+            self.show_ghost
+        }
+
+        /// Draw the trail grains leave behind them in empty cells.
+        pub fn set_show_heat(&mut self, show: bool) {
+            self.show_heat = show;
+        }
+
+        pub fn show_heat(&self) -> bool {
+            self.show_heat
+        }
+
+        /// Draw the all-time accumulation heatmap instead of the particles.
+        pub fn set_show_accumulation(&mut self, show: bool) {
+            self.show_accumulation = show;
+        }
+
+        pub fn show_accumulation(&self) -> bool {
+            self.show_accumulation
+        }
+
+        pub fn set_pixel_format(&mut self, pixel_format: PixelFormat) {
+            self.pixel_format = pixel_format;
+        }
+
+        pub fn pixel_format(&self) -> PixelFormat {
+            self.pixel_format
+        }
+
+        pub fn set_speed_tint(&mut self, speed_tint: bool) {
+            self.speed_tint = speed_tint;
+        }
+
+        pub fn speed_tint(&self) -> bool {
+            self.speed_tint
+        }
+
+        /// Eyedropper: the material under `(x, y)`, if that's on the grid and
+        /// not empty.
+        pub fn pick(&self, x: isize, y: isize) -> Option<usize> {
+            self.grid_idx(x, y)
+                .map(|i| self.particles[i].p_type)
+                .filter(|&p_type| p_type != NONE)
+        }
+
+        /// One-line, read-only description of the cell at `(x, y)`.
+        pub fn inspect(&self, x: isize, y: isize) -> Option<String> {
+            let i = self.grid_idx(x, y)?;
+            let p = &self.particles[i];
+            Some(format!(
+                "({}, {}) {} active={} velocity={:.1}",
+                x,
+                y,
+                self.materials.get(p.p_type).map_or("?", |m| &m.name),
+                p.active,
+                p.velocity
+            ))
+        }
+
+        /// Stamp `pattern` centered on `(cx, cy)` using the current brush
+        /// material. Cells falling off the grid are skipped.
+        pub fn place_pattern(&mut self, pattern: &Pattern, cx: isize, cy: isize) {
+            self.wake();
+            let left = cx.saturating_sub((pattern.width / 2) as isize);
+            let top = cy.saturating_sub((pattern.height / 2) as isize);
+            for &(x, y) in &pattern.cells {
+                let cell = (left.checked_add(x as isize), top.checked_add(y as isize));
+                let i = match cell {
+                    (Some(x), Some(y)) => self.grid_idx(x, y).filter(|&i| !self.in_margin(i)),
+                    _ => None,
+                };
+                if let Some(i) = i {
+                    self.particles[i] = Particle::new(self.active_type, true);
+                }
+            }
+        }
+
+        /// Read a plaintext `.cells` pattern (see `from_cells`) and place it
+        /// in the middle of the grid, drawn with the brush material.
+        pub fn place_cells(&mut self, text: &str) -> Result<(), ParseError> {
+            let pattern = from_cells(text)?;
+            let (cx, cy) = (self.width as isize / 2, self.height as isize / 2);
+            self.place_pattern(&pattern, cx, cy);
+            Ok(())
+        }
+
+        /// Keep a border `margin` cells deep empty, so nothing plays out right
+        /// against the edge: grains stop short of it as if it were the edge,
+        /// and nothing is drawn, emitted or randomized into it. Whatever is
+        /// there already is removed.
+        pub fn set_margin(&mut self, margin: usize) {
+            self.wake();
+            self.margin = margin;
+            for idx in 0..self.particles.len() {
+                if self.in_margin(idx) {
+                    self.particles[idx] = Particle::default();
+                }
+            }
+        }
+
+        pub fn margin(&self) -> usize {
+            self.margin
+        }
+
+        fn in_margin(&self, idx: usize) -> bool {
+            if self.margin == 0 {
+                return false;
+            }
+            let (x, y) = self.getXYfromInx(idx);
+            x < self.margin
+                || y < self.margin
+                || x.saturating_add(self.margin) >= self.width
+                || y.saturating_add(self.margin) >= self.height
+        }
+
+        /// Color `emit_line` grains by when they were made (see
+        /// `rainbow_color`), so a steady pour piles up in bands.
+        pub fn set_rainbow_emit(&mut self, rainbow: bool) {
+            self.rainbow_emit = rainbow;
+        }
+
+        pub fn rainbow_emit(&self) -> bool {
+            self.rainbow_emit
+        }
+
+        /// Drop fresh grains of the brush material into every empty cell
+        /// within `radius` of the line from `(x0, y0)` to `(x1, y1)`. Unlike
+        /// `set_line` nothing already there is touched. Returns how many
+        /// grains were added.
+        pub fn emit_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, radius: usize) -> usize {
+            profile!("emit");
+            self.wake();
+            let mut grain = Particle::new(self.active_type, true);
+            if self.rainbow_emit {
+                grain = grain.with_color(rainbow_color(self.generation));
+            }
+            // Any wider and the brush covers the whole grid anyway.
+            let r = radius.min(self.width.max(self.height)) as isize;
+            let mut emitted = 0;
+            let (start, end) = ((clamp_far(x0), clamp_far(y0)), (clamp_far(x1), clamp_far(y1)));
+            // Only the stretch of the stroke within reach of the grid.
+            let (w, h) = (self.width as isize, self.height as isize);
+            let (start, end) = match clip_line(start, end, (-r, -r), (w - 1 + r, h - 1 + r)) {
+                Some(clipped) => clipped,
+                None => return 0,
+            };
+            for (x, y) in line_drawing::Bresenham::new(start, end) {
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        if dx * dx + dy * dy > r * r {
+                            continue;
+                        }
+                        if let Some(i) = self.grid_idx(x + dx, y + dy) {
+                            if self.particles[i].p_type == NONE && !self.in_margin(i) {
+                                self.particles[i] = grain;
+                                emitted += 1;
+                            }
+                        }
+                    }
+                }
+            }
+            emitted
+        }
+
+        /// Mirror the grid left to right in place. Particles keep everything
+        /// but their position, and their heat trails go with them.
+        pub fn flip_horizontal(&mut self) {
+            self.flip(true);
+        }
+
+        /// Turn the grid upside down in place, like `flip_horizontal`.
+        pub fn flip_vertical(&mut self) {
+            self.flip(false);
+        }
+
+        fn flip(&mut self, horizontal: bool) {
+            self.wake();
+            let width = self.width;
+            flip_cells(&mut self.particles, width, horizontal);
+            flip_cells(&mut self.scratch_particles, width, horizontal);
+            flip_cells(&mut self.heat, width, horizontal);
+            flip_cells(&mut self.trail, width, horizontal);
+            flip_cells(&mut self.visits, width, horizontal);
+        }
+
+        /// Overlay `other` on this grid: each non-empty cell of `other`
+        /// replaces the cell at the same position here. If the sizes differ
+        /// only the overlapping top-left region is merged.
+        pub fn merge_layer(&mut self, other: &SandGrid) {
+            self.wake();
+            for y in 0..self.height.min(other.height) {
+                for x in 0..self.width.min(other.width) {
+                    let p = other.particles[x + y * other.width];
+                    if p.p_type != NONE && !self.in_margin(x + y * self.width) {
+                        self.particles[x + y * self.width] = p;
+                    }
+                }
+            }
+        }
+
+        /// A Life grid with a live cell wherever this grid has a particle.
+        pub fn to_life(&self) -> BitGrid {
+            let mut life = BitGrid::new_empty(self.width, self.height);
+            for (idx, p) in self.particles.iter().enumerate() {
+                if p.p_type != NONE {
+                    life.set(idx % self.width, idx / self.width, true);
+                }
+            }
+            life
+        }
+
+        /// Reseed the grid's PRNG, making `randomize` and `update` repeatable.
+        pub fn reseed(&mut self, seed: (u64, u64)) {
+            self.rng = seed.into();
+            self.base_seed = seed.0 ^ seed.1;
+        }
         pub fn clear(&mut self){
+            self.wake();
             for x in 0..self.particles.len(){
                 self.particles[x] = Particle::default();
+                self.scratch_particles[x] = Particle::default();
+                self.heat[x] = 0.0;
+                self.trail[x] = HOT;
+                self.visits[x] = 0;
             }
+            self.warmup_remaining = 0;
+            self.heat_ramp = 0;
+            self.generation = 0;
         }
         pub   fn set_brush_type(&mut self, brush_type: usize){
             self.active_type = brush_type;
         }
+
+        pub fn brush_type(&self) -> usize {
+            self.active_type
+        }
     
         pub   fn new_random(width: usize, height: usize) -> Self {
             let mut result = Self::new_empty(width, height);
@@ -49,24 +851,174 @@ pub struct SandGrid {
         
     
         pub   fn randomize(&mut self) {
-            let mut rng: randomize::PCG32 = generate_seed().into();
-            for c in self.particles.iter_mut() {
-                let mut rng1 = rand::thread_rng();
-                //println!("Integer: {}", rng1.gen_range(0..PARTICLETYPES.len());
-                *c = Particle::new(rng1.gen_range(0..PARTICLETYPES.len()), true);
+            self.randomize_with_warmup(DEFAULT_WARMUP_UPDATES);
+        }
+
+        /// Set the fraction of cells (0.0..=1.0) the next `randomize` fills.
+        pub fn set_fill_density(&mut self, density: f32) {
+            self.fill_density = density.clamp(0.0, 1.0);
+        }
+
+        pub fn fill_density(&self) -> f32 {
+            self.fill_density
+        }
+
+        /// Have `randomize` thin out its noise so at least `spacing` empty
+        /// cells separate any two particles (in every direction, diagonals
+        /// included), for an even scatter instead of clumps. 0, the default,
+        /// leaves the noise alone.
+        pub fn set_min_spacing(&mut self, spacing: usize) {
+            self.min_spacing = spacing;
+        }
+
+        pub fn min_spacing(&self) -> usize {
+            self.min_spacing
+        }
+
+        /// Remove particles until none are within `min_spacing` cells of
+        /// another. Particles are considered in a random order, and each is
+        /// kept if none already kept is too close.
+        fn thin_out(&mut self) {
+            let r = self.min_spacing as isize;
+            let mut order: Vec<usize> = (0..self.particles.len()).filter(|&i| self.particles[i].p_type != NONE).collect();
+            for i in (1..order.len()).rev() {
+                let j = self.rng.next_u32() as usize % (i + 1);
+                order.swap(i, j);
             }
+            let mut kept = vec![false; self.particles.len()];
+            for idx in order {
+                let (x, y) = self.getXYfromInx(idx);
+                let (x, y) = (x as isize, y as isize);
+                let crowded = (y - r..=y + r).any(|ny| {
+                    (x - r..=x + r).any(|nx| self.grid_idx(nx, ny).is_some_and(|n| kept[n]))
+                });
+                if crowded {
+                    self.particles[idx] = Particle::default();
+                } else {
+                    kept[idx] = true;
+                }
+            }
+        }
+
+        /// Same as `randomize`, but runs `warmup` updates instead of the default.
+        pub fn randomize_with_warmup(&mut self, warmup: usize) {
+            self.randomize_steps(warmup);
+            while self.warmup_step() {}
+            // None of the warm-up was on screen, so neither is its heat.
+            self.scale_heat(0.0);
+        }
+
+        /// Seed the grid with noise, but leave the warm-up updates to be driven
+        /// one at a time through `warmup_step`, so a big grid doesn't stall the
+        /// caller.
+        pub fn randomize_steps(&mut self, warmup: usize) {
+            self.wake();
+            for idx in 0..self.particles.len() {
+                // Rolled either way, so the margin doesn't change the noise.
+                let particle = self.random_particle(self.fill_density);
+                self.particles[idx] = if self.in_margin(idx) { Particle::default() } else { particle };
+                self.heat[idx] = 0.0;
+            }
+            if self.min_spacing > 0 {
+                self.thin_out();
+            }
+            self.generation = 0;
             // run a few simulation iterations for aesthetics (If we don't, the
             // noise is ugly)
-            for _ in 0..3 {
+            self.warmup_remaining = warmup;
+            self.warmup_total = warmup;
+            // Everything moves at once while the noise settles; ease its heat
+            // in over the warm-up and the first few updates after it.
+            self.heat_ramp = warmup + (1.0 / HEAT_RAMP_STEP).ceil() as usize;
+        }
+
+        /// Fill the rectangle between corners `(x0, y0)` and `(x1, y1)`
+        /// (inclusive, clipped to the grid) with noise, each cell filled with
+        /// probability `fill`, leaving everything outside it alone. With
+        /// `keep_walls`, cells holding something that doesn't move (magnets,
+        /// holes, ...) are left alone too. No warm-up is run.
+        pub fn randomize_region(
+            &mut self,
+            x0: isize,
+            y0: isize,
+            x1: isize,
+            y1: isize,
+            fill: f32,
+            keep_walls: bool,
+        ) {
+            self.wake();
+            let fill = fill.clamp(0.0, 1.0);
+            let clip = |a: isize, b: isize, len: usize| {
+                (a.min(b).max(0), a.max(b).min(len as isize - 1))
+            };
+            let (left, right) = clip(x0, x1, self.width);
+            let (top, bottom) = clip(y0, y1, self.height);
+            for y in top..=bottom {
+                for x in left..=right {
+                    let idx = x as usize + y as usize * self.width;
+                    let p_type = self.particles[idx].p_type;
+                    if keep_walls && p_type != NONE && self.materials.movement(p_type) == Some(Movement::Solid) {
+                        continue;
+                    }
+                    let particle = self.random_particle(fill);
+                    self.particles[idx] = if self.in_margin(idx) { Particle::default() } else { particle };
+                }
+            }
+        }
+
+        /// Noise for `randomize`: filled with probability `fill`, with sand
+        /// or water. Magnets don't make for nice noise, so only the basic
+        /// types are scattered.
+        fn random_particle(&mut self, fill: f32) -> Particle {
+            let filled = self.roll(fill);
+            let p_type = if !filled {
+                NONE
+            } else if self.coin_flip() {
+                SAND
+            } else {
+                WATER
+            };
+            Particle::new(p_type, true)
+        }
+
+        /// Run one pending warm-up update. Returns whether any remain afterwards.
+        pub fn warmup_step(&mut self) -> bool {
+            if self.warmup_remaining > 0 {
                 self.update();
+                self.warmup_remaining -= 1;
+                if self.warmup_remaining == 0 {
+                    self.generation = 0;
+                }
             }
+            self.warmup_remaining > 0
+        }
+
+        /// `(done, total)` warm-up updates if an incremental randomize is in
+        /// progress.
+        pub fn warmup_progress(&self) -> Option<(usize, usize)> {
+            if self.warmup_remaining > 0 {
+                Some((self.warmup_total - self.warmup_remaining, self.warmup_total))
+            } else {
+                None
+            }
+        }
+
+        fn coin_flip(&mut self) -> bool {
+            self.rng.next_u32() & 1 == 0
+        }
+
+        /// A coin flip that depends only on the cell, the generation and the
+        /// seed, so it doesn't matter what order cells are visited in.
+        fn cell_coin(&self, idx: usize) -> bool {
+            let (x, y) = self.getXYfromInx(idx);
+            cell_hash(x as u64, y as u64, self.generation, self.base_seed) & 1 == 0
         }
     
         pub    fn update_water(&mut self, idx: usize){
             log::debug!("{:?}", self.particles[idx]);
             //check to see if we can move down
-            let mut v: Vec<isize> = self.getEightNeighbors(idx);
-            let mut ui = v[6];
+            let v = self.getEightNeighbors(idx);
+            let ui = v[6];
             let mut ul = v[5];
             let mut ur = v[7];
             
@@ -76,10 +1028,8 @@ pub struct SandGrid {
                 self.particles[idx].already_updated = true;
             }
             else if ui > -1 && self.particles[ui as usize].active {
-                if rand::random(){
-                    ul ^= ur;
-                    ur ^= ul;
-                    ul ^= ur;
+                if self.coin_flip(){
+                    std::mem::swap(&mut ul, &mut ur);
                 }
            
                 //check bl  (which may be swapped)
@@ -103,107 +1053,755 @@ pub struct SandGrid {
                 self.particles[idx].active = true; 
                 }
             }
-            else {
-                self.particles[idx].already_updated = true;
-                self.particles[idx].active = false;
-    
-                self.particles[ui as usize].active = true;
-                self.particles[ui as usize].p_type = self.particles[idx].p_type;
-                self.particles[idx].p_type = 0;
-            }
-    
-    
+            else {
+                self.particles[idx].already_updated = true;
+                self.particles[idx].active = false;
+    
+                self.particles[ui as usize].active = true;
+                self.particles[ui as usize].p_type = self.particles[idx].p_type;
+                self.particles[idx].p_type = 0;
+            }
+    
+    
+        }
+        /// Move the particle at `from` into the cell `to`, marking it as
+        /// handled for this update. Whatever was in `to` (a lighter liquid,
+        /// say) ends up in `from`; moving into a hole removes the particle
+        /// instead.
+        fn move_particle(&mut self, from: usize, to: usize) {
+            self.stir(from);
+            self.stir(to);
+            if let Some(row) = self.flow_row {
+                if from / self.width < row && to / self.width >= row {
+                    self.flow_crossings += 1;
+                }
+            }
+            let (fx, fy) = self.getXYfromInx(from);
+            let (tx, ty) = self.getXYfromInx(to);
+            let distance = fx.max(tx) - fx.min(tx) + fy.max(ty) - fy.min(ty);
+            let path = line_drawing::Bresenham::new((fx as isize, fy as isize), (tx as isize, ty as isize));
+            let trail = self.materials.get(self.particles[from].p_type).and_then(|m| m.trail);
+            if let Some(color) = trail {
+                self.heat[from] = 1.0;
+                self.trail[from] = color;
+            }
+            for (x, y) in path.skip(1) {
+                let i = x as usize + y as usize * self.width;
+                self.visits[i] = self.visits[i].saturating_add(1);
+                if let Some(color) = trail {
+                    self.heat[i] = 1.0;
+                    self.trail[i] = color;
+                }
+            }
+            if self.hook.0.is_some() {
+                self.moves.push(Move {
+                    from: (fx, fy),
+                    to: (tx, ty),
+                    p_type: self.particles[from].p_type,
+                });
+            }
+            if self.particles[to].p_type == HOLE {
+                self.drained += 1;
+                self.particles[from] = Particle::default();
+                self.particles[from].already_updated = true;
+                return;
+            }
+            let displaced = self.particles[to];
+            self.particles[to] = self.particles[from];
+            self.particles[to].velocity = distance as f32;
+            self.particles[to].active = true;
+            self.particles[to].already_updated = true;
+            self.particles[from] = if displaced.p_type == NONE {
+                Particle::default()
+            } else {
+                displaced
+            };
+            self.particles[from].already_updated = true;
+        }
+
+        /// Whether a grain can move into `idx`: it's empty, or a hole, and not
+        /// in the margin.
+        fn is_vacant(&self, idx: usize) -> bool {
+            matches!(self.particles[idx].p_type, NONE | HOLE) && !self.in_margin(idx)
+        }
+
+        fn come_to_rest(&mut self, idx: usize) {
+            self.particles[idx].active = false;
+            self.particles[idx].already_updated = true;
+        }
+
+        /// Move the particle at `idx` according to its material's
+        /// `Movement`: powders and liquids fall, gases rise.
+        pub fn update_sand(&mut self, idx: usize) {
+            //if at base level set to inactive go to next particle
+            //if there is not a particle below it then move it down one akd keep it active
+            //if there a dir particle below it can it go left or right
+            //if so then go bl or br and set to inactince
+            //else stop and set to inactive
+            log::debug!("{:?}", self.particles[idx]);
+            let (movement, gravity, dissipation) = match self.materials.get(self.particles[idx].p_type) {
+                Some(m) if m.movement != Movement::Solid => (m.movement, m.gravity, m.dissipation),
+                _ => return self.come_to_rest(idx),
+            };
+            if dissipation > 0.0 && self.roll(dissipation) {
+                self.stir(idx);
+                self.particles[idx] = Particle::default();
+                self.particles[idx].already_updated = true;
+                self.dissipated += 1;
+                return;
+            }
+            // light materials only get to move every few updates
+            self.particles[idx].fall_timer += 1;
+            if self.particles[idx].fall_timer < gravity.fall_interval {
+                self.particles[idx].already_updated = true;
+                return;
+            }
+            self.particles[idx].fall_timer = 0;
+            //check to see if we can move down
+            let v = self.getEightNeighbors(idx);
+            // gases do everything upside down
+            let rising = movement == Movement::Gas;
+            if rising && self.rng.next_u32() % GAS_DRIFT_ODDS == 0 {
+                // random walk: sometimes drift sideways instead of rising
+                let side = if self.coin_flip() { v[4] } else { v[0] };
+                if side > -1 && self.is_vacant(side as usize) {
+                    self.move_particle(idx, side as usize);
+                    return;
+                }
+            }
+            let (bi, mut bl, mut br) = if rising { (v[6], v[5], v[7]) } else { (v[2], v[3], v[1]) };
+            //we hit the bottom
+            if bi == -1 {
+                self.come_to_rest(idx);
+            } else if self.is_vacant(bi as usize) {
+                // heavy materials keep going while the cells below are empty
+                let mut to = bi as usize;
+                for _ in 1..gravity.fall_speed.min(self.max_fall) {
+                    let next = if rising {
+                        to.checked_sub(self.width)
+                    } else {
+                        to.checked_add(self.width).filter(|&n| n < self.particles.len())
+                    };
+                    match next {
+                        Some(next) if self.particles[to].p_type == NONE && self.is_vacant(next) => {
+                            to = next
+                        }
+                        _ => break,
+                    }
+                }
+                self.move_particle(idx, to);
+            } else if !rising && (self.sticks(idx, &v) || self.pressed(idx)) {
+                self.come_to_rest(idx);
+            } else {
+                let (mut left, mut right) = (v[4], v[0]);
+                if self.cell_coin(idx) {
+                    std::mem::swap(&mut bl, &mut br);
+                    std::mem::swap(&mut left, &mut right);
+                }
+                let to = match [bl, br].iter().copied().find(|&t| t > -1 && self.is_vacant(t as usize)) {
+                    Some(to) => Some(to),
+                    // liquids and gases also spread out sideways
+                    None if movement != Movement::Powder => self.spread_towards(idx, [left, right], rising),
+                    None => None,
+                };
+                match to {
+                    Some(to) => self.move_particle(idx, to as usize),
+                    None => self.come_to_rest(idx),
+                }
+            }
+        }
+
+        /// Which of the `sides` of `idx` (left or right neighbour, first one
+        /// first) leads to the nearest cell within `SPREAD_REACH` that has
+        /// room to fall (or `rising`, rise) into, if any.
+        fn spread_towards(&self, idx: usize, sides: [isize; 2], rising: bool) -> Option<isize> {
+            let (x, y) = self.getXYfromInx(idx);
+            let (x, y) = (x as isize, y as isize);
+            let next_y = if rising { y - 1 } else { y + 1 };
+            let mut open = [true, true];
+            for d in 1..=SPREAD_REACH as isize {
+                for (side, open) in sides.iter().zip(open.iter_mut()) {
+                    if *side < 0 || !*open {
+                        continue;
+                    }
+                    let step = *side - idx as isize;
+                    let cell = self.grid_idx(x + step * d, y).filter(|&i| self.is_vacant(i));
+                    match cell {
+                        Some(_) if self.grid_idx(x + step * d, next_y).is_some_and(|i| self.is_vacant(i)) => {
+                            return Some(*side)
+                        }
+                        Some(_) => {}
+                        None => *open = false,
+                    }
+                }
+            }
+            None
+        }
+
+        /// Pull grains within `MAGNET_RADIUS` of a magnet one cell towards the
+        /// nearest magnet. Grains in range are held in place against gravity
+        /// for this update even when they can't move.
+        fn update_magnets(&mut self) {
+            let magnets: Vec<usize> = (0..self.particles.len())
+                .filter(|&idx| self.particles[idx].p_type == MAGNET)
+                .collect();
+            if magnets.is_empty() {
+                return;
+            }
+            // grain idx -> (squared distance, magnet idx) of the nearest magnet
+            let mut nearest: HashMap<usize, (isize, usize)> = HashMap::new();
+            let r = MAGNET_RADIUS as isize;
+            for &m in &magnets {
+                let (mx, my) = self.getXYfromInx(m);
+                let (mx, my) = (mx as isize, my as isize);
+                for y in (my - r)..=(my + r) {
+                    for x in (mx - r)..=(mx + r) {
+                        if let Some(g) = self.grid_idx(x, y) {
+                            if !self.materials.is_grain(self.particles[g].p_type) || !self.clear_path(g, m) {
+                                continue;
+                            }
+                            let d = (x - mx).pow(2) + (y - my).pow(2);
+                            let closer = nearest.get(&g).map_or(true, |&(best, _)| d < best);
+                            if closer {
+                                nearest.insert(g, (d, m));
+                            }
+                        }
+                    }
+                }
+            }
+            let mut moves: Vec<(usize, usize)> = Vec::with_capacity(nearest.len());
+            for (&g, &(_, m)) in &nearest {
+                self.particles[g].already_updated = true;
+                let (gx, gy) = self.getXYfromInx(g);
+                let (mx, my) = self.getXYfromInx(m);
+                let tx = gx as isize + (mx as isize - gx as isize).signum();
+                let ty = gy as isize + (my as isize - gy as isize).signum();
+                if let Some(t) = self.grid_idx(tx, ty) {
+                    moves.push((g, t));
+                }
+            }
+            // HashMap order isn't stable, so sort before letting the seeded
+            // RNG decide who wins when several grains want the same cell.
+            moves.sort_unstable();
+            for i in (1..moves.len()).rev() {
+                let j = self.rng.next_u32() as usize % (i + 1);
+                moves.swap(i, j);
+            }
+            for (g, t) in moves {
+                if self.particles[t].p_type == NONE && !self.in_margin(t) {
+                    self.move_particle(g, t);
+                } else {
+                    // held where it is
+                    self.particles[g].active = false;
+                }
+            }
+        }
+
+        /// Apply the reaction table to every pair of orthogonal neighbours.
+        /// Each cell takes part in at most one reaction per update.
+        fn update_reactions(&mut self) {
+            if self.reactions.is_empty() {
+                return;
+            }
+            let mut reacted = vec![false; self.particles.len()];
+            for idx in 0..self.particles.len() {
+                if reacted[idx] {
+                    continue;
+                }
+                let v = self.getEightNeighbors(idx);
+                for &n in &[v[0], v[2], v[4], v[6]] {
+                    if n < 0 || reacted[n as usize] {
+                        continue;
+                    }
+                    let below = n == v[2];
+                    let n = n as usize;
+                    let (a, b) = (self.particles[idx].p_type, self.particles[n].p_type);
+                    let reaction = match self.reactions.get(a, b) {
+                        Some(&reaction) if below || !reaction.above => reaction,
+                        _ => continue,
+                    };
+                    if self.roll(reaction.probability) {
+                        if (reaction.result_a, reaction.result_b) == (b, a) {
+                            // just trading places, so move them like `update` would
+                            self.move_particle(idx, n);
+                            self.particles[idx].active = true;
+                        } else {
+                            self.stir(idx);
+                            self.stir(n);
+                            self.particles[idx] = Particle::new(reaction.result_a, true);
+                            self.particles[n] = Particle::new(reaction.result_b, true);
+                        }
+                        reacted[idx] = true;
+                        reacted[n] = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        pub fn reactions(&self) -> &ReactionTable {
+            &self.reactions
+        }
+
+        pub fn reactions_mut(&mut self) -> &mut ReactionTable {
+            &mut self.reactions
+        }
+
+        /// Whether nothing but empty cells and grains lie strictly between
+        /// `from` and `to`.
+        fn clear_path(&self, from: usize, to: usize) -> bool {
+            let (fx, fy) = self.getXYfromInx(from);
+            let (tx, ty) = self.getXYfromInx(to);
+            line_drawing::Bresenham::new((fx as isize, fy as isize), (tx as isize, ty as isize))
+                .filter_map(|(x, y)| self.grid_idx(x, y))
+                .filter(|&i| i != from && i != to)
+                .all(|i| self.particles[i].p_type == NONE || self.materials.is_grain(self.particles[i].p_type))
+        }
+
+           pub  fn update(&mut self) {
+            profile!("update");
+            self.flow_crossings = 0;
+            self.drained = 0;
+            self.dissipated = 0;
+            self.moves.clear();
+            for p in self.particles.iter_mut() {
+                p.velocity = 0.0;
+            }
+            if self.show_ghost {
+                self.scratch_particles.copy_from_slice(&self.particles);
+            }
+            let heat_before = Some(self.heat.clone()).filter(|_| self.heat_ramp > 0);
+            {
+                profile!("overburden");
+                self.compute_overburden();
+            }
+            {
+                profile!("magnets");
+                self.update_magnets();
+            }
+            let due = self.due_chunks();
+            {
+                profile!("movement");
+                match self.scan_bounds().filter(|_| due.is_none()) {
+                    None => {
+                        for idx in (0..self.particles.len()).rev() {
+                            if due.as_ref().is_some_and(|due| !due[self.chunk_of(idx)]) {
+                                continue;
+                            }
+                            self.update_cell(idx);
+                        }
+                    }
+                    Some(mut bounds) => {
+                        // The same order as the full scan, bottom to top and
+                        // right to left, widening as things move so that
+                        // whatever they disturb still gets a look.
+                        let mut y = bounds.1 + bounds.3;
+                        while y > bounds.1 {
+                            y -= 1;
+                            let mut x = bounds.0 + bounds.2;
+                            while x > bounds.0 {
+                                x -= 1;
+                                self.update_cell(x + y * self.width);
+                                bounds = union_rect(bounds, self.with_margin(self.stirred));
+                            }
+                        }
+                    }
+                }
+            }
+            {
+                profile!("reactions");
+                self.update_reactions();
+            }
+            {
+                profile!("heat");
+                if let Some(before) = heat_before {
+                    for (h, b) in self.heat.iter_mut().zip(before) {
+                        *h = h.min(b + HEAT_RAMP_STEP);
+                    }
+                    self.heat_ramp -= 1;
+                }
+                self.scale_heat(self.heat_decay.update_factor());
+            }
+            for p in self.particles.iter_mut() {
+                p.already_updated = false;
+            }
+            // Skipped chunks can miss what happened next to them, so don't
+            // trust this update to say what's awake.
+            self.awake = if due.is_some() { None } else { self.restless_region() };
+            self.stirred = (0, 0, 0, 0);
+            self.flow_total += u64::from(self.flow_crossings);
+            self.generation += 1;
+            if let Some(hook) = self.hook.0.as_mut() {
+                hook(&self.moves);
+            }
+        }
+
+        /// Run `hook` after every `update` with the moves that update made.
+        /// Replaces any previous hook. Moves aren't tracked at all while no
+        /// hook is set.
+        pub fn set_update_hook<F: FnMut(&[Move]) + 'static>(&mut self, hook: F) {
+            self.hook = HookSlot(Some(Box::new(hook)));
+        }
+
+        pub fn clear_update_hook(&mut self) {
+            self.hook = HookSlot(None);
+            self.moves.clear();
+        }
+
+        /// Average position of the particles still in motion (see
+        /// `active_count`), if any.
+        pub fn center_of_mass(&self) -> Option<(f32, f32)> {
+            let (mut sx, mut sy, mut n) = (0.0, 0.0, 0);
+            for (idx, p) in self.particles.iter().enumerate() {
+                if self.in_motion(p) {
+                    let (x, y) = self.getXYfromInx(idx);
+                    sx += x as f64;
+                    sy += y as f64;
+                    n += 1;
+                }
+            }
+            if n == 0 {
+                None
+            } else {
+                Some(((sx / n as f64) as f32, (sy / n as f64) as f32))
+            }
+        }
+
+        /// Hash of the grid's size and what's in every cell, for checking
+        /// that a seeded run ends up the same on every platform (see
+        /// `--expect-hash`). Unlike the `std` hashers this is fixed, so a
+        /// value can be written down once and compared against later.
+        pub fn state_hash(&self) -> u64 {
+            let mut h = mix64(self.width as u64 ^ ((self.height as u64) << 32));
+            for p in &self.particles {
+                h = mix64(h ^ ((p.p_type as u64) << 1) ^ p.active as u64);
+            }
+            h
+        }
+
+        /// `(x, y, width, height)` of the cells the next `update` will look
+        /// at, or `None` if it has to scan the whole grid. Only cells that
+        /// moved, changed or can move by themselves (gases, say) last update
+        /// and their neighbours are awake; anything they disturb is woken as
+        /// the update goes. Edits wake the whole grid, as do magnets (which
+        /// reach further than a neighbour), cohesion and pressure (which
+        /// roll for resting grains) and activity over most of the grid,
+        /// where a full scan is as quick.
+        pub fn awake_region(&self) -> Option<(usize, usize, usize, usize)> {
+            let bounds = self.scan_bounds()?;
+            Some(bounds).filter(|_| self.due_chunks().is_none())
+        }
+
+        /// The part of the grid the movement pass has to cover (see
+        /// `awake_region`), or `None` for all of it.
+        fn scan_bounds(&self) -> Option<(usize, usize, usize, usize)> {
+            if self.cohesion > 0.0 || self.pressure > 0.0 {
+                return None;
+            }
+            let (x, y, w, h) = union_rect(self.awake?, self.with_margin(self.stirred));
+            Some((x, y, w, h)).filter(|_| 2 * w * h <= self.particles.len())
+        }
+
+        /// Everything that might move next update without being disturbed,
+        /// plus whatever changed this update, with a cell's margin around
+        /// it. `None` if there are magnets.
+        fn restless_region(&self) -> Option<(usize, usize, usize, usize)> {
+            let mut bounds = self.stirred;
+            for (idx, p) in self.particles.iter().enumerate() {
+                if p.p_type == MAGNET {
+                    return None;
+                }
+                let restless = match self.materials.get(p.p_type) {
+                    Some(m) if m.movement != Movement::Solid => {
+                        p.active
+                            || m.movement == Movement::Gas
+                            || m.dissipation > 0.0
+                            || m.gravity.fall_interval > 1
+                    }
+                    _ => false,
+                };
+                if restless {
+                    let (x, y) = self.getXYfromInx(idx);
+                    bounds = union_rect(bounds, (x, y, 1, 1));
+                }
+            }
+            Some(self.with_margin(bounds))
+        }
+
+        /// `bounds` grown by a cell each way, clipped to the grid.
+        fn with_margin(&self, (x, y, w, h): (usize, usize, usize, usize)) -> (usize, usize, usize, usize) {
+            if w == 0 || h == 0 {
+                return (0, 0, 0, 0);
+            }
+            let (x0, y0) = (x.saturating_sub(1), y.saturating_sub(1));
+            let x1 = (x + w + 1).min(self.width);
+            let y1 = (y + h + 1).min(self.height);
+            (x0, y0, x1 - x0, y1 - y0)
+        }
+
+        /// Note that the cell at `idx` changed, so its neighbours need a
+        /// look this update.
+        fn stir(&mut self, idx: usize) {
+            let (x, y) = self.getXYfromInx(idx);
+            self.stirred = union_rect(self.stirred, (x, y, 1, 1));
+        }
+
+        /// Have the next `update` look at every cell again, after the grid
+        /// (or how things move in it) was changed from outside.
+        fn wake(&mut self) {
+            self.awake = None;
+        }
+
+        /// Update the particle at `idx`, if it hasn't already this update.
+        /// Particles that don't move at all (magnets, holes, ...) just come
+        /// to rest, so they don't count as active forever after being
+        /// placed.
+        fn update_cell(&mut self, idx: usize) {
+            let p = self.particles[idx];
+            if p.already_updated {
+                return;
+            }
+            if self.materials.movement(p.p_type).is_some_and(|m| m != Movement::Solid) {
+                self.update_sand(idx);
+            } else if p.active {
+                self.come_to_rest(idx);
+            }
+        }
+
+        /// Number of non-empty cells.
+        pub fn particle_count(&self) -> usize {
+            self.particles.iter().filter(|p| p.p_type != NONE).count()
+        }
+
+        /// Number of particles still in motion: ones that moved last update,
+        /// or are waiting their turn to fall again. Particles that can't
+        /// move never count, whether or not an update has looked at them
+        /// yet.
+        pub fn active_count(&self) -> usize {
+            self.particles.iter().filter(|p| self.in_motion(p)).count()
+        }
+
+        fn in_motion(&self, p: &Particle) -> bool {
+            p.active && self.materials.movement(p.p_type).is_some_and(|m| m != Movement::Solid)
+        }
+
+        /// Skip the animation: `update` until nothing moves, or
+        /// `MAX_SETTLE_UPDATES` have run. Returns how many updates it took,
+        /// counting the last one, where everything stayed put.
+        pub fn settle(&mut self) -> usize {
+            let mut steps = 0;
+            while self.active_count() > 0 && steps < MAX_SETTLE_UPDATES {
+                self.update();
+                steps += 1;
+            }
+            steps
+        }
+
+        /// `(x, height)` of the tallest column of material, measured up from
+        /// the floor to its topmost cell. The leftmost wins a tie. Holes
+        /// don't count.
+        pub fn tallest_column(&self) -> Option<(usize, usize)> {
+            let mut tallest: Option<(usize, usize)> = None;
+            for x in 0..self.width {
+                let top = (0..self.height).find(|&y| {
+                    let p_type = self.particles[x + y * self.width].p_type;
+                    p_type != NONE && p_type != HOLE
+                });
+                if let Some(top) = top {
+                    let height = self.height - top;
+                    if tallest.map_or(true, |(_, best)| height > best) {
+                        tallest = Some((x, height));
+                    }
+                }
+            }
+            tallest
+        }
+
+        /// `(x, y)` of grains that could have moved last update but didn't:
+        /// the cell they fall into (the one above, for gases) is empty, they
+        /// weren't waiting out their fall interval and no magnet was holding
+        /// them. Anything found here points at a bug in the movement rules.
+        pub fn find_floating_grains(&self) -> Vec<(usize, usize)> {
+            let r = MAGNET_RADIUS as isize;
+            let magnets: Vec<(isize, isize)> = (0..self.particles.len())
+                .filter(|&idx| self.particles[idx].p_type == MAGNET)
+                .map(|idx| {
+                    let (x, y) = self.getXYfromInx(idx);
+                    (x as isize, y as isize)
+                })
+                .collect();
+            let mut floating = Vec::new();
+            for (idx, p) in self.particles.iter().enumerate() {
+                let movement = match self.materials.movement(p.p_type) {
+                    Some(m) if m != Movement::Solid => m,
+                    _ => continue,
+                };
+                if p.active || p.fall_timer > 0 {
+                    continue;
+                }
+                let (x, y) = self.getXYfromInx(idx);
+                let next_y = if movement == Movement::Gas { y as isize - 1 } else { y as isize + 1 };
+                let free = self.grid_idx(x as isize, next_y).is_some_and(|n| self.is_vacant(n));
+                let held = self.materials.is_grain(p.p_type)
+                    && magnets
+                        .iter()
+                        .any(|&(mx, my)| (mx - x as isize).abs() <= r && (my - y as isize).abs() <= r);
+                if free && !held {
+                    floating.push((x, y));
+                }
+            }
+            floating
+        }
+
+        /// Check the grid's internal consistency, returning a description of
+        /// every problem found. `expected_particles` is the particle count the
+        /// grid should have (e.g. the count before an `update`, which only
+        /// ever moves particles around).
+        pub fn check_invariants(&self, expected_particles: Option<usize>) -> Vec<String> {
+            let mut problems = Vec::new();
+            let size = self.width * self.height;
+            if self.particles.len() != size || self.scratch_particles.len() != size {
+                problems.push(format!(
+                    "buffer sizes {}/{} don't match {}x{}",
+                    self.particles.len(),
+                    self.scratch_particles.len(),
+                    self.width,
+                    self.height
+                ));
+            }
+            for (idx, p) in self.particles.iter().enumerate() {
+                if p.already_updated {
+                    problems.push(format!("already_updated left set at {:?}", self.getXYfromInx(idx)));
+                }
+                if p.p_type >= self.materials.len() {
+                    problems.push(format!("unknown type {} at {:?}", p.p_type, self.getXYfromInx(idx)));
+                }
+            }
+            if let Some(expected) = expected_particles {
+                let count = self.particle_count();
+                if count != expected {
+                    problems.push(format!("particle count {} != expected {}", count, expected));
+                }
+            }
+            problems
+        }
+
+        /// Log every invariant violation, and panic if `fatal` is set.
+        pub fn assert_invariants(&self, expected_particles: Option<usize>, fatal: bool) {
+            let problems = self.check_invariants(expected_particles);
+            for problem in &problems {
+                log::error!("Invariant violated: {}", problem);
+            }
+            if fatal && !problems.is_empty() {
+                panic!("{} grid invariant(s) violated", problems.len());
+            }
+        }
+
+        /// Count grains moving from above `row` to on/below it. `None` turns
+        /// the measurement off. Resets the running total.
+        pub fn set_flow_row(&mut self, row: Option<usize>) {
+            self.flow_row = row.filter(|&r| r < self.height);
+            self.flow_total = 0;
+            self.flow_crossings = 0;
+        }
+
+        pub fn flow_row(&self) -> Option<usize> {
+            self.flow_row
+        }
+
+        /// Grains that crossed the measurement row during the last `update`.
+        pub fn flow_crossings(&self) -> u32 {
+            self.flow_crossings
+        }
+
+        /// Grains that crossed the measurement row since it was set.
+        pub fn flow_total(&self) -> u64 {
+            self.flow_total
+        }
+
+        /// Updates run since the grid was created, loaded, cleared or
+        /// randomized (counting from the end of the warm-up).
+        pub fn generation(&self) -> u64 {
+            self.generation
+        }
+
+        /// Open (or close) the cell at `(x, y)`, e.g. a column of the bottom
+        /// row to drain sand out through. Opening replaces whatever was there.
+        pub fn set_open(&mut self, x: isize, y: isize, open: bool) {
+            self.wake();
+            if let Some(i) = self.grid_idx(x, y) {
+                if open {
+                    self.particles[i] = Particle::new(HOLE, false);
+                } else if self.particles[i].p_type == HOLE {
+                    self.particles[i] = Particle::default();
+                }
+            }
         }
-        pub fn update_sand(&mut self) {
-            let mut counter = 0;
-            for idx in (0..self.particles.len()).rev() {
-                //let neibs = self.count_neibs(x, y);
-                //println!("Checking for alive cell at index {:?}", self.particles[idx]);
-                //self.printCrazy8(v, idx);
-    
-                if !self.particles[idx].already_updated && self.particles[idx].active && self.particles[idx].p_type ==1  {
-                    
-                    //if at base level set to inactive go to next particle
-                    //if there is not a particle below it then move it down one akd keep it active
-                    //if there a dir particle below it can it go left or right
-                    //if so then go bl or br and set to inactince
-                    //else stop and set to inactive
-    
-                    
-                    counter+=1;
-                    //println!("executing loop counter {}",counter);
-                    log::debug!("{:?}", self.particles[idx]);
-                    //check to see if we can move down
-                    let mut v: Vec<isize> = self.getEightNeighbors(idx);
-                    let mut bi = v[2] as isize;
-                    //we hit the bottom
-                    if bi == -1 {
-                        self.particles[idx].active = false;
-                        self.particles[idx].already_updated = true;
-                    } else if  self.particles[bi as usize].p_type == 0{
-                        self.particles[idx].active = false;
-                        self.particles[idx].already_updated = true;
-                        self.particles[idx].p_type =0;
-                        self.particles[bi as usize].active = true;
-                        self.particles[bi as usize].already_updated = false;
-                        self.particles[bi as usize].p_type =1;
-                    } else if self.particles[bi as usize].p_type == 1 && self.particles[bi as usize].active == false {
-                        let mut bl = v[3] as isize;
-                        let mut br = v[1] as isize;
-                        if rand::random() {
-                                bl ^= br;
-                                br ^= bl;
-                                bl ^= br;
-                        }
-                        if  bl > -1 && self.particles[bl as usize].p_type == 0  {
-                            self.particles[idx].active = false;
-                            self.particles[idx].already_updated = true;
-                            self.particles[idx].p_type =0;
-                            self.particles[bl as usize].active = true;
-                            self.particles[bl as usize].already_updated = false;
-                            self.particles[bl as usize].p_type =1;
-                        }
-                        else if  br > -1 && self.particles[br as usize].p_type == 0{
-                            self.particles[idx].active = false;
-                            self.particles[idx].already_updated = true;
-                            self.particles[idx].p_type =0;
-                            self.particles[br as usize].active = true;
-                            self.particles[br as usize].already_updated = false;
-                            self.particles[br as usize].p_type =1;
-                        }
-                         else  {
-                             self.particles[idx].active = false;
-                            self.particles[idx].already_updated = true;
-                        }
-                    }
-                    else {
-                        println!("come here often?");
-                        self.particles[idx].active = false;
-                        self.particles[idx].already_updated = true;
-                        self.particles[idx].p_type =0;
-                        self.particles[bi as usize].active = true;
-                        self.particles[bi as usize].already_updated = false;
-                        self.particles[bi as usize].p_type =1;
-                    }
-                    
-                }    
+
+        pub fn is_open(&self, x: isize, y: isize) -> bool {
+            self.grid_idx(x, y).is_some_and(|i| self.particles[i].p_type == HOLE)
+        }
+
+        /// The particle at `(x, y)`, or `None` off the grid.
+        pub fn get(&self, x: isize, y: isize) -> Option<Particle> {
+            self.grid_idx(x, y).map(|i| self.particles[i])
+        }
+
+        /// Put `particle` at `(x, y)`, replacing whatever was there. Off the
+        /// grid, or anything but an empty cell in the margin, this does
+        /// nothing. It takes part in the next `update` as if it had always
+        /// been there.
+        pub fn set(&mut self, x: isize, y: isize, particle: Particle) {
+            self.wake();
+            let i = self
+                .grid_idx(x, y)
+                .filter(|&i| particle.p_type == NONE || !self.in_margin(i));
+            if let Some(i) = i {
+                self.particles[i] = Particle {
+                    already_updated: false,
+                    ..particle
+                };
             }
         }
-    
-           pub  fn update(&mut self) {
-            for idx in (0..self.particles.len()).rev() {
-                   if !self.particles[idx].already_updated && self.particles[idx].active {
-    
-                    if self.particles[idx].p_type ==1 {
-                        self.update_sand();
-                    }
-                   }
+
+        /// Swap whatever is at `(x, y)` with the cell `(dx, dy)` away from it,
+        /// physics or not, for setting up a scene by hand. Returns whether
+        /// anything moved: nothing does if either cell is off the grid or in
+        /// the margin.
+        pub fn nudge(&mut self, x: isize, y: isize, dx: isize, dy: isize) -> bool {
+            let to = match (x.checked_add(dx), y.checked_add(dy)) {
+                (Some(tx), Some(ty)) => self.grid_idx(tx, ty).filter(|&i| !self.in_margin(i)),
+                _ => None,
+            };
+            let from = self.grid_idx(x, y).filter(|&i| !self.in_margin(i));
+            match (from, to) {
+                (Some(from), Some(to)) => {
+                    self.wake();
+                    self.particles.swap(from, to);
+                    true
+                }
+                _ => false,
             }
-   
+        }
+
+        /// The material at `(x, y)` (`"none"` for an empty cell), or `None`
+        /// off the grid or for a type the registry doesn't know.
+        pub fn material_at(&self, x: isize, y: isize) -> Option<&Material> {
+            self.get(x, y).and_then(|p| self.materials.get(p.p_type))
+        }
+
+        /// Grains that fell into a hole during the last `update`.
+        pub fn drained(&self) -> u32 {
+            self.drained
+        }
 
+        /// Gas particles that faded away during the last `update`.
+        pub fn dissipated(&self) -> u32 {
+            self.dissipated
         }
     
       pub  fn toggle(&mut self, x: isize, y: isize) -> bool {
+            self.wake();
             if let Some(i) = self.grid_idx(x, y) {
                 let was_alive = self.particles[i].active;
                 self.particles[i].set_active(!was_alive);
@@ -213,15 +1811,59 @@ pub struct SandGrid {
             }
         }
 
+        /// `draw` into a new buffer of the right size (4 bytes per cell, in
+        /// the grid's pixel format).
+        pub fn render_to_vec(&self) -> Vec<u8> {
+            let mut screen = vec![0; 4 * self.particles.len()];
+            self.draw(&mut screen);
+            screen
+        }
+
        pub fn draw(&self, screen: &mut [u8]) {
-            debug_assert_eq!(screen.len(), 4 * self.particles.len());
-            for (c, pix) in self.particles.iter().zip(screen.chunks_exact_mut(4)) {
-                let color = if c.p_type == 1 {
-                    [0, 0xff, 0xff, 0xff]
+            profile!("draw");
+            // A mismatched buffer (e.g. mid-resize) just gets the overlapping
+            // part drawn; the zips below stop at whichever side runs out.
+            if screen.len() != 4 * self.particles.len() {
+                log::warn!(
+                    "draw: buffer is {} bytes, expected {} for {}x{}",
+                    screen.len(),
+                    4 * self.particles.len(),
+                    self.width,
+                    self.height
+                );
+            }
+            if self.show_accumulation {
+                let max = self.visits.iter().copied().max().unwrap_or(0);
+                for (&count, pix) in self.visits.iter().zip(screen.chunks_exact_mut(4)) {
+                    pix.copy_from_slice(&self.pixel_format.encode(accumulation_color(count, max)));
+                }
+                return;
+            }
+            for (idx, (c, pix)) in self.particles.iter().zip(screen.chunks_exact_mut(4)).enumerate() {
+                let ghost = Some(&self.scratch_particles[idx])
+                    .filter(|prev| self.show_ghost && prev.p_type != c.p_type)
+                    .and_then(|prev| self.color_of(prev.p_type));
+                let material = c.color.filter(|_| c.p_type != NONE).or_else(|| self.color_of(c.p_type));
+                let background = match ghost {
+                    _ if Some(idx / self.width) == self.flow_row => [0x40, 0x40, 0x40, 0xff],
+                    Some(previous) if material.is_none() => ghost_color([0, 0, 0, 0xff], previous),
+                    _ if material.is_none() && c.p_type == NONE && self.show_heat => {
+                        blend([0, 0, 0, 0xff], self.trail[idx], self.heat[idx])
+                    }
+                    _ => [0, 0, 0x00, 0xff],
+                };
+                // translucent materials (like smoke) show what's behind them
+                let color = match material {
+                    Some(color) if color[3] == 0xff => color,
+                    Some(color) => blend(background, [color[0], color[1], color[2], 0xff], f32::from(color[3]) / 255.0),
+                    None => background,
+                };
+                let color = if self.speed_tint && c.p_type != NONE {
+                    speed_color(color, c.velocity)
                 } else {
-                    [0, 0, 0x00, 0xff]
+                    color
                 };
-                pix.copy_from_slice(&color);
+                pix.copy_from_slice(&self.pixel_format.encode(color));
             }
         }
 
@@ -259,22 +1901,28 @@ pub struct SandGrid {
         // }
     
       pub   fn set_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, active: bool) {
+            self.wake();
             // probably should do sutherland-hodgeman if this were more serious.
             // instead just clamp the start pos, and draw until moving towards the
             // end pos takes us out of bounds.
             let x0 = x0.max(0).min(self.width as isize);
             let y0 = y0.max(0).min(self.height as isize);
-            for (x, y) in line_drawing::Bresenham::new((x0, y0), (x1, y1)) {
+            for (x, y) in line_drawing::Bresenham::new((x0, y0), (clamp_far(x1), clamp_far(y1))) {
                 if let Some(i) = self.grid_idx(x, y) {
+                    if self.in_margin(i) {
+                        continue;
+                    }
                     self.particles[i].set_active(active);
                     self.particles[i].already_updated = false;
                     self.particles[i].p_type = self.active_type;
+                    self.particles[i].color = None;
                 } else {
                     break;
                 }
             }
         }
     
+        #[allow(non_snake_case)]
         pub  fn getXYfromInx(&self, idx: usize) -> (usize, usize) {
             let row: usize = idx % self.width;
             let column: usize = idx / self.width;
@@ -300,20 +1948,19 @@ pub struct SandGrid {
             0  1  2
             3  4  5
             6  7  8
-            The returned vector is <5,8,7,6,3,0,1,2>
+            The returned array is <5,8,7,6,3,0,1,2>
     
             Example 2) Cell X is in the upper left corner of a 3x3 board
             idx = 0 and the board would look like this, remember -1 indicataes a wall or edge
             -1 -1 -1
             -1  0  1
             -1  3  4
-            The returned vector is <1,4,3,-1,-1,-1,-1,-1>
+            The returned array is <1,4,3,-1,-1,-1,-1,-1>
             */
-            pub     fn getEightNeighbors(&self, idx: usize) -> Vec<isize> {
+            #[allow(non_snake_case)]
+            pub     fn getEightNeighbors(&self, idx: usize) -> [isize; 8] {
             let coord = self.getXYfromInx(idx);
-            let mut v: Vec<isize> = vec![-1; 8];
-            log::info!("[getEightNeighbors] Called with indx:{}, which maps to x,y:{:?}",idx, coord);
-            //println!("{}{:?}",idx, coord );
+            let mut v = [-1_isize; 8];
     
             v[0] = if coord.0 == self.width-1 { -1 } else { (idx+1) as isize };
             v[1] = if coord.0 == self.width-1 || coord.1 == self.height -1 { -1 } else {(idx + 1 + self.width) as isize};
@@ -327,7 +1974,8 @@ pub struct SandGrid {
             v
         }
     
-        pub   fn printCrazy8(&self, x: Vec<isize>, idx: usize){
+        #[allow(non_snake_case)]
+        pub   fn printCrazy8(&self, x: [isize; 8], idx: usize){
     
             println!();
             println!("{} {} {}",x[5], x[6], x[7] );
@@ -335,11 +1983,11 @@ pub struct SandGrid {
             println!("{} {} {}",x[3],x[2], x[1]);
             println!();
         }
-    
+
         pub  fn grid_idx<I: std::convert::TryInto<usize>>(&self, x: I, y: I) -> Option<usize> {
             if let (Ok(x), Ok(y)) = (x.try_into(), y.try_into()) {
                 if x < self.width && y < self.height {
-                    Some(x + y * self.width)
+                    y.checked_mul(self.width).and_then(|row| row.checked_add(x))
                 } else {
                     None
                 }
@@ -348,8 +1996,133 @@ pub struct SandGrid {
             }
         }
     }
+    impl Automaton for SandGrid {
+        fn update(&mut self) {
+            SandGrid::update(self)
+        }
+        fn draw(&self, screen: &mut [u8]) {
+            SandGrid::draw(self, screen)
+        }
+        fn toggle(&mut self, x: isize, y: isize) -> bool {
+            SandGrid::toggle(self, x, y)
+        }
+        fn set_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, active: bool) {
+            SandGrid::set_line(self, x0, y0, x1, y1, active)
+        }
+        fn clear(&mut self) {
+            SandGrid::clear(self)
+        }
+    }
+
+/// Whether a 32-bit random `draw` comes in under `chance`, 0.0..=1.0,
+/// decided with integer math alone. `chance` becomes a threshold out of
+/// 2^32 exactly (any f32 times a power of two fits in an f64), so unlike
+/// turning the draw into a float the outcome can't hinge on rounding.
+pub fn roll_under(draw: u32, chance: f32) -> bool {
+    let threshold = (f64::from(chance.clamp(0.0, 1.0)) * 4_294_967_296.0) as u64;
+    u64::from(draw) < threshold
+}
+
+/// Pull a line endpoint in far enough that the line drawing's arithmetic
+/// can't overflow. Anything this far out is off the grid either way.
+fn clamp_far(v: isize) -> isize {
+    v.clamp(isize::MIN / 4, isize::MAX / 4)
+}
+
+/// The part of the line from `start` to `end` inside the rectangle between
+/// corners `min` and `max` (inclusive), or `None` if it misses. A line
+/// already inside comes back untouched; otherwise the new ends are rounded
+/// to the nearest cell, so a line coming in from far away costs no more to
+/// draw than one that starts nearby.
+fn clip_line(
+    start: (isize, isize),
+    end: (isize, isize),
+    min: (isize, isize),
+    max: (isize, isize),
+) -> Option<((isize, isize), (isize, isize))> {
+    let inside = |(x, y): (isize, isize)| (min.0..=max.0).contains(&x) && (min.1..=max.1).contains(&y);
+    if inside(start) && inside(end) {
+        return Some((start, end));
+    }
+    // Liang-Barsky: narrow the range of t along start + t * (end - start).
+    let (x0, y0) = (start.0 as f64, start.1 as f64);
+    let (dx, dy) = (end.0 as f64 - x0, end.1 as f64 - y0);
+    let (mut t0, mut t1) = (0.0_f64, 1.0_f64);
+    let edges = [
+        (-dx, x0 - min.0 as f64),
+        (dx, max.0 as f64 - x0),
+        (-dy, y0 - min.1 as f64),
+        (dy, max.1 as f64 - y0),
+    ];
+    for &(p, q) in &edges {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    let at = |t: f64| ((x0 + t * dx).round() as isize, (y0 + t * dy).round() as isize);
+    Some((at(t0), at(t1)))
+}
+
+/// Mirror a row-major grid of cells, `width` wide, in place: left to right
+/// if `horizontal`, otherwise top to bottom.
+fn flip_cells<T>(cells: &mut [T], width: usize, horizontal: bool) {
+    if horizontal {
+        for row in cells.chunks_exact_mut(width) {
+            row.reverse();
+        }
+        return;
+    }
+    let height = cells.len() / width;
+    for y in 0..height / 2 {
+        let (top, bottom) = cells.split_at_mut((height - 1 - y) * width);
+        top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+    }
+}
+
+/// Smallest `(x, y, width, height)` rectangle holding both `a` and `b`, either
+/// of which may be empty (no width or height).
+fn union_rect(a: (usize, usize, usize, usize), b: (usize, usize, usize, usize)) -> (usize, usize, usize, usize) {
+    if a.2 == 0 || a.3 == 0 {
+        return b;
+    }
+    if b.2 == 0 || b.3 == 0 {
+        return a;
+    }
+    let (x0, y0) = (a.0.min(b.0), a.1.min(b.1));
+    let (x1, y1) = ((a.0 + a.2).max(b.0 + b.2), (a.1 + a.3).max(b.1 + b.3));
+    (x0, y0, x1 - x0, y1 - y0)
+}
+
+/// splitmix64's finalizer: every input bit affects every output bit.
+fn mix64(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Well-mixed hash of a cell position, frame and seed, for per-cell random
+/// choices. Each input goes through the finalizer separately (rather than,
+/// say, `x + y * width`) so that neighbouring cells and consecutive frames
+/// come out uncorrelated.
+pub fn cell_hash(x: u64, y: u64, frame: u64, seed: u64) -> u64 {
+    const GOLDEN: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut h = mix64(seed.wrapping_add(GOLDEN));
+    h = mix64(h ^ x.wrapping_add(GOLDEN));
+    h = mix64(h ^ y.wrapping_add(GOLDEN.wrapping_mul(2)));
+    mix64(h ^ frame.wrapping_add(GOLDEN.wrapping_mul(3)))
+}
+
     /// Generate a pseudorandom seed for the game's PRNG.
-fn generate_seed() -> (u64, u64) {
+pub(crate) fn generate_seed() -> (u64, u64) {
     use byteorder::{ByteOrder, NativeEndian};
     use getrandom::getrandom;
 
@@ -370,6 +2143,10 @@ fn generate_seed() -> (u64, u64) {
             active: bool,
             already_updated: bool,
             velocity: f32,
+            // updates since this particle last got to fall (see `Gravity`)
+            fall_timer: u8,
+            // drawn instead of the material's color, e.g. for a rainbow pour
+            color: Option<[u8; 4]>,
         }
         
         impl Particle {
@@ -378,9 +2155,22 @@ fn generate_seed() -> (u64, u64) {
                     p_type,
                     active,
                     already_updated: false,
-                    velocity: 1.0,
+                    velocity: 0.0,
+                    fall_timer: 0,
+                    color: None,
                 }
             }
+
+            /// The same particle, drawn in `color` instead of its material's.
+            #[must_use]
+            pub fn with_color(mut self, color: [u8; 4]) -> Self {
+                self.color = Some(color);
+                self
+            }
+
+            pub fn color(&self) -> Option<[u8; 4]> {
+                self.color
+            }
         
             #[must_use]
             pub   fn next_state(mut self, active: bool) -> Self {
@@ -391,4 +2181,874 @@ fn generate_seed() -> (u64, u64) {
             pub    fn set_active(&mut self, active: bool) {
                 *self = self.next_state(active);
             }
-        }
\ No newline at end of file
+
+            pub fn p_type(&self) -> usize {
+                self.p_type
+            }
+
+            pub fn is_active(&self) -> bool {
+                self.active
+            }
+
+            pub fn velocity(&self) -> f32 {
+                self.velocity
+            }
+        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incremental_randomize_matches_synchronous() {
+        let mut sync = SandGrid::new_empty(40, 30);
+        sync.reseed((7, 9));
+        let mut steps = sync.clone();
+        sync.randomize_with_warmup(5);
+        steps.randomize_steps(5);
+        assert_eq!(steps.warmup_progress(), Some((0, 5)));
+        let mut driven = 1;
+        while steps.warmup_step() {
+            driven += 1;
+        }
+        assert_eq!(driven, 5);
+        assert_eq!(steps.warmup_progress(), None);
+        assert_eq!(steps.state_hash(), sync.state_hash());
+    }
+
+    /// A `width` by `height` grid with grains of `p_type` at `cells`.
+    fn grid_with(width: usize, height: usize, p_type: usize, cells: &[(isize, isize)]) -> SandGrid {
+        let mut grid = SandGrid::new_empty(width, height);
+        grid.reseed((1, 2));
+        for &(x, y) in cells {
+            grid.set(x, y, Particle::new(p_type, true));
+        }
+        grid
+    }
+
+    fn occupied(grid: &SandGrid) -> Vec<(isize, isize)> {
+        let mut cells = Vec::new();
+        for y in 0..grid.height() as isize {
+            for x in 0..grid.width() as isize {
+                if grid.get(x, y).is_some_and(|p| p.p_type() != NONE) {
+                    cells.push((x, y));
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn magnet_pulls_powder_in() {
+        let mut grid = grid_with(12, 12, MAGNET, &[(6, 6)]);
+        grid.set(3, 6, Particle::new(SAND, true));
+        grid.set(8, 4, Particle::new(DUST, true));
+        // Out of reach, so it just falls.
+        grid.set(0, 0, Particle::new(SAND, true));
+        grid.update();
+        assert_eq!(grid.pick(4, 6), Some(SAND));
+        assert_eq!(grid.pick(7, 5), Some(DUST));
+        assert_eq!(grid.pick(0, 0), None);
+        grid.settle();
+        // Held against the magnet, not fallen to the floor.
+        assert_eq!(grid.pick(5, 6), Some(SAND));
+        assert_eq!(grid.pick(7, 5), Some(DUST));
+        assert_eq!(grid.pick(0, 11), Some(SAND));
+    }
+
+    #[test]
+    fn flow_counts_each_grain_crossing_once() {
+        let mut grid = grid_with(1, 20, SAND, &[(0, 0), (0, 1), (0, 2), (0, 15)]);
+        grid.set_flow_row(Some(10));
+        let mut crossings = 0;
+        for _ in 0..30 {
+            grid.update();
+            crossings += grid.flow_crossings();
+        }
+        // The grain that started below the row never crossed it.
+        assert_eq!(crossings, 3);
+        assert_eq!(grid.flow_total(), 3);
+        grid.set_flow_row(Some(10));
+        assert_eq!(grid.flow_total(), 0);
+    }
+
+    #[test]
+    fn heavy_grains_fall_several_cells_an_update() {
+        let speed = GRAVITY[SAND].fall_speed;
+        let mut grid = grid_with(2, 12, SAND, &[(0, 0)]);
+        grid.set(1, 0, Particle::new(DUST, true));
+        grid.update();
+        assert_eq!(grid.pick(0, speed as isize), Some(SAND));
+        // Dust is light, and only falls once every few updates.
+        assert_eq!(grid.pick(1, 0), Some(DUST));
+        for _ in 1..GRAVITY[DUST].fall_interval {
+            grid.update();
+        }
+        assert_eq!(grid.pick(1, 1), Some(DUST));
+        assert_eq!(grid.pick(0, 3 * speed as isize), Some(SAND));
+    }
+
+    #[test]
+    fn cohesion_keeps_a_clump_on_its_ledge() {
+        // A 6x6 clump sitting flush with the right end of a ledge.
+        let clump: Vec<_> = (4..10).flat_map(|y| (10..16).map(move |x| (x, y))).collect();
+        let still_on_ledge = |cohesion: f32| {
+            let mut grid = grid_with(30, 20, SAND, &clump);
+            grid.materials_mut().load_str("ledge #808080 9.0 solid 0.0").unwrap();
+            let ledge = grid.materials().by_name("ledge").unwrap();
+            for x in 8..16 {
+                grid.set(x, 10, Particle::new(ledge, false));
+            }
+            grid.set_cohesion(cohesion);
+            for _ in 0..5 {
+                grid.update();
+            }
+            occupied(&grid).iter().filter(|&&(x, y)| y < 10 && (8..16).contains(&x)).count()
+        };
+        // Loose sand pours off the end; wet sand mostly stays put.
+        let (loose, wet) = (still_on_ledge(0.0), still_on_ledge(1.0));
+        assert!(wet > clump.len() * 3 / 4, "{} of {} left", wet, clump.len());
+        assert!(loose < clump.len() * 2 / 3, "{} of {} left", loose, clump.len());
+    }
+
+    #[test]
+    fn pick_reads_the_material_under_the_cursor() {
+        let mut grid = grid_with(4, 4, MAGNET, &[(1, 2)]);
+        grid.set(3, 0, Particle::new(DUST, false));
+        assert_eq!(grid.pick(1, 2), Some(MAGNET));
+        assert_eq!(grid.pick(3, 0), Some(DUST));
+        assert_eq!(grid.pick(2, 2), None);
+        for &(x, y) in &[(-1, 0), (0, -1), (4, 0), (0, 4)] {
+            assert_eq!(grid.pick(x, y), None);
+        }
+    }
+
+    #[test]
+    fn slides_go_either_way_evenly() {
+        // A grain landing on another slides off one side or the other.
+        let (mut left, mut right) = (0_i32, 0_i32);
+        for seed in 0..2000 {
+            let mut grid = SandGrid::new_empty(3, 2);
+            grid.reseed((seed, 0));
+            grid.set(1, 0, Particle::new(SAND, true));
+            grid.set(1, 1, Particle::new(WATER, false));
+            grid.update();
+            match (grid.pick(0, 1), grid.pick(2, 1)) {
+                (Some(SAND), None) => left += 1,
+                (None, Some(SAND)) => right += 1,
+                other => panic!("didn't slide: {:?}", other),
+            }
+        }
+        assert!((left - right).abs() < 150, "{} left, {} right", left, right);
+    }
+
+    #[test]
+    fn cell_hash_bits_are_balanced() {
+        let mut ones = [0_u32; 2];
+        for frame in 0..8 {
+            for y in 0..64 {
+                for x in 0..64 {
+                    let h = cell_hash(x, y, frame, 42);
+                    ones[0] += (h & 1) as u32;
+                    // Neighbours agree about half the time too.
+                    ones[1] += ((h ^ cell_hash(x + 1, y, frame, 42)) & 1) as u32;
+                }
+            }
+        }
+        for &n in &ones {
+            assert!((15_800..17_000).contains(&n), "{} of 32768", n);
+        }
+    }
+
+    #[test]
+    fn speed_tint_ramps_up_to_hot() {
+        let base = [0, 0, 0, 0xff];
+        assert_eq!(speed_color(base, 0.0), base);
+        assert_eq!(speed_color(base, MAX_TINT_SPEED / 2.0), [0x80, 0x30, 0, 0xff]);
+        assert_eq!(speed_color(base, MAX_TINT_SPEED), HOT);
+        assert_eq!(speed_color(base, 2.0 * MAX_TINT_SPEED), HOT);
+        // Grains lying still are drawn in their own color.
+        let mut grid = grid_with(1, 2, SAND, &[(0, 0)]);
+        grid.set_speed_tint(true);
+        grid.update();
+        let moving = grid.render_to_vec();
+        grid.update();
+        let sand = grid.materials().get(SAND).unwrap().color.unwrap();
+        assert_eq!(grid.render_to_vec()[4..], sand);
+        assert_eq!(moving[4..], speed_color(sand, 1.0));
+    }
+
+    #[test]
+    fn invariants_hold_through_updates() {
+        let mut grid = SandGrid::new_empty(30, 30);
+        grid.reseed((1, 2));
+        grid.randomize();
+        for _ in 0..20 {
+            let before = grid.particle_count();
+            grid.update();
+            assert_eq!(grid.check_invariants(Some(before)), Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn corruption_trips_the_invariants() {
+        let mut grid = grid_with(10, 10, SAND, &[(1, 1)]);
+        grid.particles[5].already_updated = true;
+        grid.particles[7].p_type = 200;
+        grid.scratch_particles.pop();
+        let problems = grid.check_invariants(Some(5));
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+    }
+
+    #[test]
+    #[should_panic(expected = "invariant")]
+    fn fatal_invariants_panic() {
+        let mut grid = SandGrid::new_empty(10, 10);
+        grid.particles[3].p_type = 200;
+        grid.assert_invariants(None, true);
+    }
+
+    #[test]
+    fn update_hook_sees_every_move() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        let mut grid = grid_with(10, 10, SAND, &[(1, 0), (4, 1), (7, 0), (7, 3)]);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&seen);
+        grid.set_update_hook(move |moves| sink.borrow_mut().push(moves.to_vec()));
+        for _ in 0..3 {
+            let mut expected = occupied(&grid);
+            grid.update();
+            let moves = seen.borrow_mut().pop().unwrap();
+            assert!(!moves.is_empty());
+            for m in moves {
+                assert_eq!(m.p_type, SAND);
+                let from = (m.from.0 as isize, m.from.1 as isize);
+                expected.retain(|&c| c != from);
+                expected.push((m.to.0 as isize, m.to.1 as isize));
+            }
+            expected.sort_by_key(|&(x, y)| (y, x));
+            assert_eq!(expected, occupied(&grid));
+        }
+        assert!(seen.borrow().is_empty());
+        grid.clear_update_hook();
+        grid.update();
+        assert!(seen.borrow().is_empty());
+    }
+
+    #[test]
+    fn pixel_formats_order_bytes() {
+        assert_eq!(PixelFormat::Rgba.encode([1, 2, 3, 4]), [1, 2, 3, 4]);
+        assert_eq!(PixelFormat::Bgra.encode([1, 2, 3, 4]), [3, 2, 1, 4]);
+
+        let mut grid = grid_with(2, 1, MAGNET, &[(1, 0)]);
+        assert_eq!(&grid.render_to_vec()[4..], &[0xc0, 0x20, 0x20, 0xff]);
+        grid.set_pixel_format(PixelFormat::Bgra);
+        assert_eq!(&grid.render_to_vec()[4..], &[0x20, 0x20, 0xc0, 0xff]);
+    }
+
+    #[test]
+    fn visit_counts_only_grow_along_each_move() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        let mut grid = grid_with(6, 12, SAND, &[(1, 0), (1, 1), (4, 0)]);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&seen);
+        grid.set_update_hook(move |moves| *sink.borrow_mut() = moves.to_vec());
+        let counts = |grid: &SandGrid| {
+            let mut counts = Vec::new();
+            for y in 0..12 {
+                for x in 0..6 {
+                    counts.push(grid.visits(x, y).unwrap());
+                }
+            }
+            counts
+        };
+        for _ in 0..8 {
+            let mut expected = counts(&grid);
+            grid.update();
+            for m in seen.borrow().iter() {
+                let from = (m.from.0 as isize, m.from.1 as isize);
+                let to = (m.to.0 as isize, m.to.1 as isize);
+                for (x, y) in line_drawing::Bresenham::new(from, to).skip(1) {
+                    expected[x as usize + y as usize * 6] += 1;
+                }
+            }
+            assert_eq!(counts(&grid), expected);
+        }
+        assert!(counts(&grid).iter().any(|&v| v > 1));
+    }
+
+    #[test]
+    fn undersized_draw_buffer_warns_instead_of_panicking() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static DRAW_WARNINGS: AtomicUsize = AtomicUsize::new(0);
+        struct CountDrawWarnings;
+        impl log::Log for CountDrawWarnings {
+            fn enabled(&self, metadata: &log::Metadata) -> bool {
+                metadata.level() <= log::Level::Warn
+            }
+            fn log(&self, record: &log::Record) {
+                if self.enabled(record.metadata()) && record.args().to_string().starts_with("draw:") {
+                    DRAW_WARNINGS.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+            fn flush(&self) {}
+        }
+        let _ = log::set_logger(&CountDrawWarnings);
+        log::set_max_level(log::LevelFilter::Warn);
+
+        let grid = grid_with(4, 4, SAND, &[(0, 0), (3, 3)]);
+        let mut small = vec![0; 4 * 4 * 2];
+        grid.draw(&mut small);
+        assert_eq!(DRAW_WARNINGS.load(Ordering::SeqCst), 1);
+        // The part that fits still gets drawn.
+        assert_eq!(small[..16], grid.render_to_vec()[..16]);
+        grid.draw(&mut []);
+        assert_eq!(DRAW_WARNINGS.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn merge_layer_only_copies_filled_cells() {
+        let mut base = grid_with(4, 3, SAND, &[(0, 0), (1, 0), (2, 2)]);
+        let mut layer = grid_with(3, 4, WATER, &[(1, 0), (2, 1)]);
+        layer.set(0, 3, Particle::new(MAGNET, true));
+        base.merge_layer(&layer);
+        let kind = |x, y| base.get(x, y).unwrap().p_type();
+        // Filled layer cells win, empty ones leave the base alone.
+        assert_eq!(kind(1, 0), WATER);
+        assert_eq!(kind(2, 1), WATER);
+        assert_eq!(kind(0, 0), SAND);
+        assert_eq!(kind(2, 2), SAND);
+        assert_eq!(occupied(&base), vec![(0, 0), (1, 0), (2, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn grains_reaching_a_hole_are_removed() {
+        let mut grid = grid_with(1, 8, SAND, &[(0, 0), (0, 1), (0, 2), (0, 3)]);
+        grid.set_open(0, 7, true);
+        let mut drained = 0;
+        for _ in 0..20 {
+            grid.update();
+            drained += grid.drained();
+        }
+        assert_eq!(drained, 4);
+        assert_eq!(occupied(&grid), vec![(0, 7)]);
+        assert!(grid.is_open(0, 7));
+    }
+
+    #[test]
+    fn drizzling_emits_a_row_of_grains_each_update() {
+        let mut grid = SandGrid::new_empty(12, 40);
+        grid.set_brush_type(SAND);
+        let mut emitted = 0;
+        for _ in 0..5 {
+            emitted += grid.emit_line(1, 0, 10, 0, 0);
+            grid.update();
+        }
+        assert_eq!(emitted, 10 * 5);
+        assert_eq!(occupied(&grid).len(), 10 * 5);
+        // Only empty cells get grains.
+        assert_eq!(grid.emit_line(1, 0, 10, 0, 0), 10);
+        assert_eq!(grid.emit_line(1, 0, 10, 0, 0), 0);
+    }
+
+    #[test]
+    fn max_fall_caps_each_update() {
+        let mut grid = grid_with(1, 40, SAND, &[(0, 0)]);
+        grid.materials_mut().get_mut(SAND).unwrap().gravity.fall_speed = 8;
+        grid.set_max_fall(3);
+        let mut y = 0;
+        for _ in 0..20 {
+            grid.update();
+            let now = occupied(&grid)[0].1;
+            assert!(now - y <= 3);
+            y = now;
+        }
+        assert_eq!(y, 39);
+        // Uncapped it falls at its own speed.
+        let mut grid = grid_with(1, 40, SAND, &[(0, 0)]);
+        grid.materials_mut().get_mut(SAND).unwrap().gravity.fall_speed = 8;
+        grid.update();
+        assert_eq!(occupied(&grid), vec![(0, 8)]);
+        grid.set_max_fall(0);
+        grid.update();
+        assert_eq!(occupied(&grid), vec![(0, 9)]);
+    }
+
+    #[test]
+    fn state_hash_is_stable() {
+        let grid = grid_with(4, 3, SAND, &[(1, 1), (3, 2)]);
+        assert_eq!(grid.state_hash(), 0x48bc_3494_c68e_96e4);
+        let mut grid = SandGrid::new_empty(64, 48);
+        grid.reseed((1, 2));
+        grid.randomize();
+        for _ in 0..100 {
+            grid.update();
+        }
+        // The same on every platform; if this changes, so has the
+        // simulation, and the README's `--expect-hash` example needs
+        // updating too.
+        assert_eq!(grid.state_hash(), 0x694c_3831_4dda_70aa);
+    }
+
+    #[test]
+    fn roll_under_thresholds_exactly() {
+        assert!(!roll_under(0, 0.0));
+        assert!(roll_under(u32::MAX, 1.0));
+        assert!(roll_under(0x7fff_ffff, 0.5));
+        assert!(!roll_under(0x8000_0000, 0.5));
+        assert!(roll_under(0x3fff_ffff, 0.25) && !roll_under(0x4000_0000, 0.25));
+    }
+
+    #[test]
+    fn awake_scan_matches_full_scan() {
+        let mut bounded = SandGrid::new_empty(60, 40);
+        bounded.reseed((3, 5));
+        bounded.randomize();
+        let mut full = bounded.clone();
+        let mut narrowed = false;
+        for step in 0..300 {
+            if step % 50 == 0 {
+                // Pour a little of each material in, somewhere new.
+                let x = (step / 50 * 9) as isize;
+                for grid in [&mut bounded, &mut full].iter_mut() {
+                    grid.set_brush_type([SAND, DUST, SMOKE][step / 50 % 3]);
+                    grid.emit_line(x, 5, x + 3, 5, 1);
+                }
+            }
+            narrowed |= bounded.awake_region().is_some();
+            full.wake();
+            bounded.update();
+            full.update();
+            assert_eq!(bounded.state_hash(), full.state_hash(), "step {}", step);
+        }
+        assert!(narrowed);
+    }
+
+    #[test]
+    fn margin_stays_empty() {
+        let mut grid = SandGrid::new_empty(10, 10);
+        grid.set_margin(2);
+        grid.set(1, 5, Particle::new(SAND, true));
+        grid.set(5, 9, Particle::new(MAGNET, true));
+        assert_eq!(grid.particle_count(), 0);
+        grid.set(2, 5, Particle::new(SAND, true));
+        assert!(!grid.nudge(2, 5, -1, 0));
+        assert!(grid.nudge(2, 5, 1, 0));
+        // Nor can an empty cell be swapped in from the margin.
+        assert!(!grid.nudge(1, 1, 1, 1));
+        assert_eq!(occupied(&grid), vec![(3, 5)]);
+        grid.settle();
+        assert_eq!(occupied(&grid), vec![(3, 7)]);
+    }
+
+    #[test]
+    fn extreme_coordinates_dont_overflow() {
+        let (lo, hi) = (isize::MIN, isize::MAX);
+        let mut grid = SandGrid::new_empty(20, 10);
+        assert_eq!(grid.grid_idx(u64::MAX, 0), None);
+        assert_eq!(grid.grid_idx(i64::MIN, i64::MIN), None);
+        assert!(!grid.toggle(hi, lo));
+        grid.set(lo, hi, Particle::new(SAND, true));
+        assert!(grid.get(hi, hi).is_none());
+        grid.set_line(lo, lo, hi, hi, true);
+        grid.set_line(hi, 0, lo, 0, true);
+        // Comes in from far away, crossing the grid's diagonal.
+        assert!(grid.emit_line(lo, lo, hi, hi, usize::MAX) > 0);
+        assert_eq!(grid.emit_line(lo, 0, lo, hi, 3), 0);
+        assert!(!grid.nudge(0, 0, hi, hi));
+        assert!(!grid.nudge(hi, hi, lo, lo));
+        let pattern = Pattern {
+            width: usize::MAX,
+            height: usize::MAX,
+            cells: vec![(0, 0), (usize::MAX, usize::MAX)],
+        };
+        grid.place_pattern(&pattern, hi, lo);
+        grid.place_pattern(&pattern, lo, hi);
+        grid.set_margin(usize::MAX);
+        assert_eq!(grid.particle_count(), 0);
+        grid.update();
+    }
+
+    #[test]
+    fn clipped_lines_stay_on_course() {
+        let (min, max) = ((0, 0), (9, 9));
+        assert_eq!(clip_line((1, 2), (8, 3), min, max), Some(((1, 2), (8, 3))));
+        assert_eq!(clip_line((-10, 5), (20, 5), min, max), Some(((0, 5), (9, 5))));
+        assert_eq!(clip_line((-5, -5), (15, 15), min, max), Some(((0, 0), (9, 9))));
+        assert_eq!(clip_line((-5, 0), (0, -5), min, max), None);
+        assert_eq!(clip_line((12, 0), (12, 9), min, max), None);
+    }
+
+    #[test]
+    fn settle_collapses_a_column() {
+        let mut grid = grid_with(5, 6, SAND, &[(2, 2), (2, 3), (2, 4), (2, 5)]);
+        // Two updates to slide the top grains off either side, and one
+        // where nothing moves.
+        assert_eq!(grid.settle(), 3);
+        assert_eq!(occupied(&grid), vec![(2, 4), (1, 5), (2, 5), (3, 5)]);
+        assert_eq!(grid.active_count(), 0);
+    }
+
+    #[test]
+    fn settle_stops_for_solids() {
+        for &solid in &[MAGNET, HOLE] {
+            let mut grid = grid_with(5, 5, solid, &[(2, 2), (4, 4)]);
+            assert_eq!(grid.settle(), 0);
+            grid.set(0, 0, Particle::new(SAND, true));
+            assert!(grid.settle() < 10);
+            assert_eq!(grid.active_count(), 0);
+        }
+    }
+
+    #[test]
+    fn active_count_skips_what_cant_move() {
+        let mut grid = grid_with(5, 5, HOLE, &[(0, 0), (4, 4)]);
+        grid.set(2, 2, Particle::new(MAGNET, true));
+        grid.set(3, 0, Particle::new(SAND, true));
+        assert_eq!(grid.active_count(), 1);
+        assert_eq!(grid.center_of_mass(), Some((3.0, 0.0)));
+    }
+
+    #[test]
+    fn settle_finishes_after_randomize() {
+        let mut grid = SandGrid::new_empty(40, 30);
+        grid.reseed((1, 2));
+        grid.randomize();
+        assert!(grid.settle() < MAX_SETTLE_UPDATES);
+        assert_eq!(grid.active_count(), 0);
+    }
+
+    #[test]
+    fn buried_grains_hold_while_the_surface_slides() {
+        let piles = |pressure| {
+            // A grain on a grain, and a column of six, both on the floor.
+            let mut cells = vec![(2, 10), (2, 11)];
+            cells.extend((6..12).map(|y| (6, y)));
+            let mut grid = grid_with(9, 12, SAND, &cells);
+            grid.set_pressure(pressure);
+            grid.update();
+            grid
+        };
+        let grid = piles(0.25);
+        assert_eq!(grid.overburden(6, 10), Some(4));
+        assert_eq!(grid.pick(2, 10), None);
+        assert_eq!((grid.pick(5, 11), grid.pick(7, 11)), (None, None));
+        // Without pressure the bottom of the column slides off too.
+        let grid = piles(0.0);
+        assert!(grid.pick(5, 11) == Some(SAND) || grid.pick(7, 11) == Some(SAND));
+    }
+
+    #[test]
+    fn ghost_shows_where_grains_just_were() {
+        let mut grid = grid_with(1, 4, SAND, &[(0, 0)]);
+        grid.set_show_ghost(true);
+        grid.update();
+        assert_eq!(occupied(&grid), vec![(0, 2)]);
+        let screen = grid.render_to_vec();
+        // 35% of the way from black to sand's cyan.
+        assert_eq!(screen[..4], [0, 0x59, 0x59, 0xff]);
+        assert_eq!(screen[4..8], [0, 0, 0, 0xff]);
+        assert_eq!(screen[8..12], [0, 0xff, 0xff, 0xff]);
+        grid.set_show_ghost(false);
+        assert_eq!(grid.render_to_vec()[..4], [0, 0, 0, 0xff]);
+    }
+
+    #[test]
+    fn fill_density_sets_how_much_randomize_fills() {
+        let mut grid = SandGrid::new_empty(100, 100);
+        for &density in &[0.3, 0.8] {
+            grid.reseed((1, 2));
+            grid.set_fill_density(density);
+            grid.randomize();
+            let filled = occupied(&grid).len() as f32 / 10_000.0;
+            assert!((filled - density).abs() < 0.02, "{} filled at {}", filled, density);
+        }
+        grid.set_fill_density(1.5);
+        assert_eq!(grid.fill_density(), 1.0);
+    }
+
+    #[test]
+    fn smoke_rises_and_fades_away() {
+        let cells: Vec<_> = (0..20).flat_map(|x| (25..30).map(move |y| (x, y))).collect();
+        let mut grid = grid_with(20, 30, SMOKE, &cells);
+        let mean_height = |grid: &SandGrid| {
+            let cells = occupied(grid);
+            cells.iter().map(|&(_, y)| y as f32).sum::<f32>() / cells.len() as f32
+        };
+        let start = mean_height(&grid);
+        for _ in 0..20 {
+            grid.update();
+        }
+        assert!(mean_height(&grid) < start - 5.0);
+        let mut left = occupied(&grid).len();
+        for _ in 0..2000 {
+            grid.update();
+            let now = occupied(&grid).len();
+            assert!(now <= left);
+            left = now;
+        }
+        assert_eq!(left, 0);
+    }
+
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn update_and_draw_open_spans() {
+        use std::sync::{Arc, Mutex};
+        use tracing::span::{Attributes, Id, Record};
+
+        struct SpanNames(Arc<Mutex<Vec<&'static str>>>);
+        impl tracing::Subscriber for SpanNames {
+            fn enabled(&self, _: &tracing::Metadata) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes) -> Id {
+                let mut names = self.0.lock().unwrap();
+                names.push(span.metadata().name());
+                Id::from_u64(names.len() as u64)
+            }
+            fn record(&self, _: &Id, _: &Record) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &tracing::Event) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let mut grid = grid_with(8, 8, SAND, &[(1, 1), (4, 2)]);
+        tracing::subscriber::with_default(SpanNames(Arc::clone(&names)), || {
+            grid.update();
+            grid.render_to_vec();
+        });
+        let names = names.lock().unwrap();
+        for name in &["update", "reactions", "heat", "draw"] {
+            assert!(names.contains(name), "no {:?} span in {:?}", name, names);
+        }
+    }
+
+    #[test]
+    fn randomize_region_leaves_the_rest_alone() {
+        let mut grid = SandGrid::new_empty(20, 20);
+        grid.reseed((1, 2));
+        grid.randomize();
+        let before = grid.clone();
+        grid.randomize_region(15, 12, 5, 4, 0.5, false);
+        let mut changed = false;
+        for y in 0..20 {
+            for x in 0..20 {
+                let (now, was) = (grid.get(x, y), before.get(x, y));
+                if (5..=15).contains(&x) && (4..=12).contains(&y) {
+                    changed |= now.map(|p| p.p_type()) != was.map(|p| p.p_type());
+                } else {
+                    assert_eq!(format!("{:?}", now), format!("{:?}", was), "({}, {})", x, y);
+                }
+            }
+        }
+        assert!(changed);
+    }
+
+    #[test]
+    fn floating_grains_are_found() {
+        let mut grid = SandGrid::new_empty(6, 6);
+        // Resting on the floor, and on another grain: both fine.
+        grid.set(1, 5, Particle::new(SAND, false));
+        grid.set(1, 4, Particle::new(SAND, false));
+        assert!(grid.find_floating_grains().is_empty());
+        // Inactive in mid-air is a bug; still falling isn't.
+        grid.set(4, 2, Particle::new(SAND, false));
+        grid.set(3, 1, Particle::new(SAND, true));
+        assert_eq!(grid.find_floating_grains(), vec![(4, 2)]);
+        // Nor is a grain a magnet is holding up.
+        grid.set(5, 0, Particle::new(MAGNET, false));
+        assert!(grid.find_floating_grains().is_empty());
+    }
+
+    #[test]
+    fn settled_offscreen_chunks_update_less_often() {
+        let mut grid = SandGrid::new_empty(4 * CHUNK_SIZE, 2 * CHUNK_SIZE);
+        grid.set_visible_region(Some((0, 0, CHUNK_SIZE, CHUNK_SIZE)));
+        grid.set_offscreen_interval(4);
+        // Every chunk updates on multiples of the interval, so start off one.
+        grid.update();
+        // Neither grain is moving yet, but both have room to fall.
+        grid.set(4, 2, Particle::new(SAND, false));
+        grid.set(56, 2, Particle::new(SAND, false));
+        let height = |grid: &SandGrid, x| (0..32).find(|&y| grid.pick(x, y).is_some());
+        for expected in &[4, 6, 8] {
+            grid.update();
+            assert_eq!(height(&grid, 4), Some(*expected));
+            assert_eq!(height(&grid, 56), Some(2), "generation {}", grid.generation());
+        }
+        assert_eq!(grid.generation() % 4, 0);
+        grid.update();
+        assert_eq!(height(&grid, 56), Some(4));
+    }
+
+    #[test]
+    fn heat_ramps_in_after_randomize() {
+        let mut grid = SandGrid::new_empty(40, 40);
+        grid.reseed((1, 2));
+        grid.randomize();
+        let heat = |grid: &SandGrid| -> Vec<f32> {
+            (0..40).flat_map(|y| (0..40).map(move |x| (x, y))).map(|(x, y)| grid.heat(x, y).unwrap()).collect()
+        };
+        let mut before = heat(&grid);
+        assert!(before.iter().all(|&h| h == 0.0));
+        let mut biggest = 0.0_f32;
+        // The ramp lasts until heat could have reached 1.0.
+        for step in 0..(1.0 / HEAT_RAMP_STEP) as usize {
+            grid.update();
+            let now = heat(&grid);
+            for (n, b) in now.iter().zip(&before) {
+                assert!(n - b <= HEAT_RAMP_STEP + 1e-6, "rose {} in update {}", n - b, step);
+                biggest = biggest.max(n - b);
+            }
+            before = now;
+        }
+        assert!(biggest > 0.0);
+    }
+
+    #[test]
+    fn get_returns_what_set_put() {
+        let mut grid = SandGrid::new_empty(5, 4);
+        let tinted = Particle::new(DUST, false).with_color([1, 2, 3, 0xff]);
+        grid.set(3, 2, tinted);
+        let p = grid.get(3, 2).unwrap();
+        assert_eq!((p.p_type(), p.is_active(), p.color()), (DUST, false, Some([1, 2, 3, 0xff])));
+        assert_eq!(grid.material_at(3, 2).unwrap().name, "dust");
+        assert_eq!(grid.material_at(0, 0).unwrap().name, "none");
+        grid.set(3, 2, Particle::default());
+        assert_eq!(grid.get(3, 2).unwrap().p_type(), NONE);
+    }
+
+    #[test]
+    fn off_the_grid_is_none_and_a_no_op() {
+        let mut grid = SandGrid::new_empty(5, 4);
+        for &(x, y) in &[(-1, 0), (0, -1), (5, 0), (0, 4), (5, 4)] {
+            grid.set(x, y, Particle::new(SAND, true));
+            assert!(grid.get(x, y).is_none(), "({}, {})", x, y);
+            assert!(grid.material_at(x, y).is_none());
+        }
+        assert!(occupied(&grid).is_empty());
+    }
+
+    #[test]
+    fn min_spacing_keeps_particles_apart() {
+        let mut grid = SandGrid::new_empty(40, 30);
+        grid.reseed((1, 2));
+        grid.set_min_spacing(2);
+        // Before the warm-up gets a chance to pile anything up.
+        grid.randomize_with_warmup(0);
+        let cells = occupied(&grid);
+        assert!(cells.len() > 20);
+        for (i, &(ax, ay)) in cells.iter().enumerate() {
+            for &(bx, by) in &cells[i + 1..] {
+                assert!((ax - bx).abs().max((ay - by).abs()) > 2, "({}, {}) and ({}, {})", ax, ay, bx, by);
+            }
+        }
+    }
+
+    #[test]
+    fn generation_counts_updates_since_a_reset() {
+        let mut grid = SandGrid::new_empty(20, 20);
+        grid.reseed((1, 2));
+        grid.randomize();
+        assert_eq!(grid.generation(), 0);
+        for expected in 1..=5 {
+            grid.update();
+            assert_eq!(grid.generation(), expected);
+        }
+        let cells = || (0..20).flat_map(|y| (0..20).map(move |x| (x, y)));
+        assert!(cells().any(|(x, y)| grid.visits(x, y) != Some(0)));
+        grid.clear();
+        assert_eq!(grid.generation(), 0);
+        assert!(cells().all(|(x, y)| grid.visits(x, y) == Some(0) && grid.heat(x, y) == Some(0.0)));
+        // Nothing left of the old run in the accumulation view either.
+        grid.set_show_accumulation(true);
+        let screen = grid.render_to_vec();
+        assert!(screen.chunks(4).all(|pix| pix == &screen[..4]));
+    }
+
+    #[test]
+    fn render_to_vec_draws_every_cell() {
+        let mut grid = SandGrid::new_empty(7, 5);
+        grid.set(6, 4, Particle::new(MAGNET, false));
+        let screen = grid.render_to_vec();
+        assert_eq!(screen.len(), 4 * 7 * 5);
+        let i = 4 * (6 + 4 * 7);
+        assert_eq!(screen[i..i + 4], [0xc0, 0x20, 0x20, 0xff]);
+        assert_eq!(screen[..4], [0, 0, 0, 0xff]);
+    }
+
+    #[test]
+    fn rainbow_grains_change_color_over_time() {
+        let mut grid = SandGrid::new_empty(10, 10);
+        grid.set_rainbow_emit(true);
+        grid.emit_line(0, 0, 0, 0, 0);
+        for _ in 0..RAINBOW_PERIOD / 3 {
+            grid.update();
+        }
+        grid.emit_line(9, 0, 9, 0, 0);
+        let colors: Vec<_> = occupied(&grid).iter().map(|&(x, y)| grid.get(x, y).unwrap().color()).collect();
+        assert_eq!(colors.len(), 2);
+        assert!(colors[0].is_some() && colors[1].is_some());
+        assert_ne!(colors[0], colors[1]);
+        // Every update gets its own shade, coming back round after a period.
+        assert_ne!(rainbow_color(0), rainbow_color(1));
+        assert_eq!(rainbow_color(5), rainbow_color(5 + RAINBOW_PERIOD));
+    }
+
+    #[test]
+    fn nudge_swaps_two_cells() {
+        let mut grid = grid_with(4, 4, SAND, &[(1, 1)]);
+        grid.set(2, 1, Particle::new(MAGNET, true));
+        assert!(grid.nudge(1, 1, 1, 0));
+        assert_eq!(grid.get(1, 1).map(|p| p.p_type()), Some(MAGNET));
+        assert_eq!(grid.get(2, 1).map(|p| p.p_type()), Some(SAND));
+        // Into an empty cell just moves the grain.
+        assert!(grid.nudge(2, 1, 0, 2));
+        assert_eq!(occupied(&grid), vec![(1, 1), (2, 3)]);
+    }
+
+    #[test]
+    fn nudging_off_the_edge_does_nothing() {
+        let mut grid = grid_with(4, 4, SAND, &[(0, 0), (3, 3)]);
+        assert!(!grid.nudge(0, 0, -1, 0));
+        assert!(!grid.nudge(0, 0, 0, -1));
+        assert!(!grid.nudge(3, 3, 1, 0));
+        assert!(!grid.nudge(3, 3, 0, 1));
+        assert!(!grid.nudge(4, 3, -1, 0));
+        assert_eq!(occupied(&grid), vec![(0, 0), (3, 3)]);
+    }
+
+    #[test]
+    fn place_cells_centres_the_pattern() {
+        let mut grid = SandGrid::new_empty(10, 8);
+        grid.set_brush_type(MAGNET);
+        grid.place_cells("!Name: hook\n.O\nO.\nOO\n").unwrap();
+        // A 2x3 pattern centred on (5, 4) starts at (4, 3).
+        assert_eq!(occupied(&grid), vec![(5, 3), (4, 4), (4, 5), (5, 5)]);
+        assert_eq!(grid.get(5, 3).map(|p| p.p_type()), Some(MAGNET));
+        assert!(grid.place_cells("OX\n").is_err());
+    }
+
+    #[test]
+    fn flips_mirror_cells_and_their_heat() {
+        let mut grid = grid_with(4, 3, MAGNET, &[(0, 0), (1, 0), (0, 1)]);
+        let hot = |x: usize, y: usize| x + y * 4;
+        grid.heat[hot(1, 0)] = 0.5;
+        grid.trail[hot(0, 1)] = [1, 2, 3, 4];
+        grid.flip_horizontal();
+        assert_eq!(occupied(&grid), vec![(2, 0), (3, 0), (3, 1)]);
+        assert_eq!(grid.heat(2, 0), Some(0.5));
+        assert_eq!(grid.trail[hot(3, 1)], [1, 2, 3, 4]);
+        grid.flip_vertical();
+        assert_eq!(occupied(&grid), vec![(3, 1), (2, 2), (3, 2)]);
+        assert_eq!(grid.heat(2, 2), Some(0.5));
+        assert_eq!(grid.trail[hot(3, 1)], [1, 2, 3, 4]);
+        grid.flip_horizontal();
+        grid.flip_vertical();
+        assert_eq!(occupied(&grid), vec![(0, 0), (1, 0), (0, 1)]);
+        assert_eq!(grid.heat(1, 0), Some(0.5));
+    }
+}
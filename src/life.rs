@@ -0,0 +1,476 @@
+use crate::{clamp_far, generate_seed, Automaton, Pattern, PixelFormat};
+
+/// Color for a live cell with `count` live neighbours: lonely cells are
+/// blue, stable ones (2 or 3) green, and crowded ones red through white.
+pub fn neighbor_color(count: usize) -> [u8; 4] {
+    const RAMP: [[u8; 4]; 9] = [
+        [0x20, 0x40, 0xff, 0xff],
+        [0x20, 0x90, 0xff, 0xff],
+        [0x20, 0xe0, 0x60, 0xff],
+        [0x80, 0xff, 0x40, 0xff],
+        [0xff, 0xd0, 0x20, 0xff],
+        [0xff, 0x80, 0x20, 0xff],
+        [0xff, 0x30, 0x20, 0xff],
+        [0xff, 0x90, 0x90, 0xff],
+        [0xff, 0xff, 0xff, 0xff],
+    ];
+    RAMP[count.min(8)]
+}
+
+/// Which rules a `BitGrid` plays by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rules {
+    /// Plain Life.
+    #[default]
+    Life,
+    /// Second-order Life: the next generation is Life applied to this one,
+    /// XORed with the one before. Keeping the previous generation around is
+    /// enough to run it backwards exactly (see `step_back`).
+    Reversible,
+}
+
+/// Game of Life (B3/S23, dead edges) stored one bit per cell, 64 cells to a
+/// word. Rows are padded out to a whole number of words; the padding bits are
+/// always kept dead.
+pub struct BitGrid {
+    cells: Vec<u64>,
+    scratch: Vec<u64>,
+    // The generation before `cells`, for the reversible rules. All dead
+    // otherwise.
+    previous: Vec<u64>,
+    rules: Rules,
+    width: usize,
+    height: usize,
+    words_per_row: usize,
+    rng: randomize::PCG32,
+    pixel_format: PixelFormat,
+    // Draw live cells by neighbour count instead of plain white.
+    color_by_neighbors: bool,
+}
+
+impl BitGrid {
+    pub fn new_empty(width: usize, height: usize) -> Self {
+        assert!(width != 0 && height != 0);
+        let words_per_row = (width + 63) / 64;
+        let size = words_per_row.checked_mul(height).expect("too big");
+        Self {
+            cells: vec![0; size],
+            scratch: vec![0; size],
+            previous: vec![0; size],
+            rules: Rules::default(),
+            width,
+            height,
+            words_per_row,
+            rng: generate_seed().into(),
+            pixel_format: PixelFormat::default(),
+            color_by_neighbors: false,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn set_pixel_format(&mut self, pixel_format: PixelFormat) {
+        self.pixel_format = pixel_format;
+    }
+
+    /// Color live cells with `neighbor_color` rather than white.
+    pub fn set_color_by_neighbors(&mut self, enabled: bool) {
+        self.color_by_neighbors = enabled;
+    }
+
+    pub fn color_by_neighbors(&self) -> bool {
+        self.color_by_neighbors
+    }
+
+    /// Switch rules. The generation before this one is forgotten (taken to
+    /// be all dead).
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.rules = rules;
+        self.forget_previous();
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    fn forget_previous(&mut self) {
+        for w in self.previous.iter_mut() {
+            *w = 0;
+        }
+    }
+
+    /// Reseed the PRNG used by `randomize`.
+    pub fn reseed(&mut self, seed: (u64, u64)) {
+        self.rng = seed.into();
+    }
+
+    fn bit(&self, x: usize, y: usize) -> (usize, u64) {
+        (y * self.words_per_row + x / 64, 1 << (x % 64))
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        let (w, mask) = self.bit(x, y);
+        self.cells[w] & mask != 0
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, alive: bool) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let (w, mask) = self.bit(x, y);
+        if alive {
+            self.cells[w] |= mask;
+        } else {
+            self.cells[w] &= !mask;
+        }
+    }
+
+    pub fn count_neibs(&self, x: usize, y: usize) -> usize {
+        let mut n = 0;
+        for dy in -1..=1_isize {
+            for dx in -1..=1_isize {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+                let (nx, ny) = (x as isize + dx, y as isize + dy);
+                if nx >= 0 && ny >= 0 && self.get(nx as usize, ny as usize) {
+                    n += 1;
+                }
+            }
+        }
+        n
+    }
+
+    pub fn alive_count(&self) -> usize {
+        self.cells.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Mask of the bits in word `w` of a row that are actual cells.
+    fn row_mask(&self, w: usize) -> u64 {
+        let used = self.width - w * 64;
+        if used >= 64 {
+            !0
+        } else {
+            (1 << used) - 1
+        }
+    }
+
+    fn word(&self, row: isize, w: isize) -> u64 {
+        if row < 0 || row >= self.height as isize || w < 0 || w >= self.words_per_row as isize {
+            0
+        } else {
+            self.cells[row as usize * self.words_per_row + w as usize]
+        }
+    }
+
+    /// The three words of `row` around `w`, shifted so bit `i` lines up with
+    /// the cell to the left, at, and to the right of cell `i`.
+    fn row_neighbors(&self, row: isize, w: isize) -> [u64; 3] {
+        let (prev, cur, next) = (self.word(row, w - 1), self.word(row, w), self.word(row, w + 1));
+        [(cur << 1) | (prev >> 63), cur, (cur >> 1) | (next << 63)]
+    }
+
+    pub fn update(&mut self) {
+        profile!("life_update");
+        self.life_into_scratch();
+        if self.rules == Rules::Reversible {
+            for (next, &before) in self.scratch.iter_mut().zip(&self.previous) {
+                *next ^= before;
+            }
+            std::mem::swap(&mut self.previous, &mut self.cells);
+        }
+        std::mem::swap(&mut self.scratch, &mut self.cells);
+    }
+
+    /// Undo one `update` under the reversible rules, returning whether it
+    /// could. Other rules lose information, so can't be stepped back.
+    pub fn step_back(&mut self) -> bool {
+        if self.rules != Rules::Reversible {
+            return false;
+        }
+        // update went (before, now) -> (now, life(now) ^ before), so
+        // (now, next) goes back to (life(now) ^ next, now).
+        std::mem::swap(&mut self.cells, &mut self.previous);
+        self.life_into_scratch();
+        for (before, &next) in self.scratch.iter_mut().zip(&self.previous) {
+            *before ^= next;
+        }
+        std::mem::swap(&mut self.previous, &mut self.scratch);
+        true
+    }
+
+    /// Write the Life generation after `cells` into `scratch`.
+    fn life_into_scratch(&mut self) {
+        for y in 0..self.height {
+            let row = y as isize;
+            for w in 0..self.words_per_row {
+                let col = w as isize;
+                let above = self.row_neighbors(row - 1, col);
+                let here = self.row_neighbors(row, col);
+                let below = self.row_neighbors(row + 1, col);
+                let inputs = [
+                    above[0], above[1], above[2], here[0], here[2], below[0], below[1], below[2],
+                ];
+                // Bit-sliced counter: `ones`/`twos` hold the count mod 4, and
+                // `fours` sticks once any lane reaches 4.
+                let (mut ones, mut twos, mut fours) = (0_u64, 0_u64, 0_u64);
+                for x in inputs.iter() {
+                    let carry = ones & x;
+                    ones ^= x;
+                    fours |= twos & carry;
+                    twos ^= carry;
+                }
+                let alive = here[1];
+                let next = twos & !fours & (ones | alive);
+                self.scratch[y * self.words_per_row + w] = next & self.row_mask(w);
+            }
+        }
+    }
+
+    pub fn randomize(&mut self) {
+        for y in 0..self.height {
+            for w in 0..self.words_per_row {
+                let word = (u64::from(self.rng.next_u32()) << 32) | u64::from(self.rng.next_u32());
+                self.cells[y * self.words_per_row + w] = word & self.row_mask(w);
+            }
+        }
+        self.forget_previous();
+    }
+
+    pub fn clear(&mut self) {
+        for w in self.cells.iter_mut() {
+            *w = 0;
+        }
+        self.forget_previous();
+    }
+
+    pub fn toggle(&mut self, x: isize, y: isize) -> bool {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return false;
+        }
+        let alive = !self.get(x as usize, y as usize);
+        self.set(x as usize, y as usize, alive);
+        alive
+    }
+
+    pub fn set_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, alive: bool) {
+        let x0 = x0.max(0).min(self.width as isize);
+        let y0 = y0.max(0).min(self.height as isize);
+        for (x, y) in line_drawing::Bresenham::new((x0, y0), (clamp_far(x1), clamp_far(y1))) {
+            if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+                break;
+            }
+            self.set(x as usize, y as usize, alive);
+        }
+    }
+
+    /// Bring `pattern` to life centered on `(cx, cy)`. Cells falling off the
+    /// grid are skipped.
+    pub fn place_pattern(&mut self, pattern: &Pattern, cx: isize, cy: isize) {
+        let left = cx.saturating_sub((pattern.width / 2) as isize);
+        let top = cy.saturating_sub((pattern.height / 2) as isize);
+        for &(x, y) in &pattern.cells {
+            if let (Some(x), Some(y)) = (left.checked_add(x as isize), top.checked_add(y as isize)) {
+                if x >= 0 && y >= 0 {
+                    self.set(x as usize, y as usize, true);
+                }
+            }
+        }
+    }
+
+    pub fn draw(&self, screen: &mut [u8]) {
+        profile!("life_draw");
+        let expected = 4 * self.width * self.height;
+        if screen.len() != expected {
+            log::warn!("draw: buffer is {} bytes, expected {}", screen.len(), expected);
+        }
+        for (i, pix) in screen.chunks_exact_mut(4).take(self.width * self.height).enumerate() {
+            let (x, y) = (i % self.width, i / self.width);
+            let color = if !self.get(x, y) {
+                [0, 0, 0, 0xff]
+            } else if self.color_by_neighbors {
+                neighbor_color(self.count_neibs(x, y))
+            } else {
+                [0xff, 0xff, 0xff, 0xff]
+            };
+            pix.copy_from_slice(&self.pixel_format.encode(color));
+        }
+    }
+}
+
+impl Automaton for BitGrid {
+    fn update(&mut self) {
+        BitGrid::update(self)
+    }
+    fn draw(&self, screen: &mut [u8]) {
+        BitGrid::draw(self, screen)
+    }
+    fn toggle(&mut self, x: isize, y: isize) -> bool {
+        BitGrid::toggle(self, x, y)
+    }
+    fn set_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, active: bool) {
+        BitGrid::set_line(self, x0, y0, x1, y1, active)
+    }
+    fn clear(&mut self) {
+        BitGrid::clear(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GLIDER: [(usize, usize); 5] = [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)];
+
+    fn live_cells(grid: &BitGrid) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                if grid.get(x, y) {
+                    cells.push((x, y));
+                }
+            }
+        }
+        cells
+    }
+
+    /// One generation of Life, a cell at a time.
+    fn naive_step(grid: &BitGrid) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                let n = grid.count_neibs(x, y);
+                if n == 3 || (n == 2 && grid.get(x, y)) {
+                    cells.push((x, y));
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn glider_glides_across_words() {
+        // Starts just left of the boundary between the first two words of
+        // each row.
+        let mut grid = BitGrid::new_empty(130, 20);
+        for &(x, y) in &GLIDER {
+            grid.set(60 + x, 2 + y, true);
+        }
+        for generation in 1..=16 {
+            let expected = naive_step(&grid);
+            grid.update();
+            assert_eq!(live_cells(&grid), expected, "generation {}", generation);
+        }
+        // A glider moves a cell diagonally every four generations.
+        let mut moved: Vec<_> = GLIDER.iter().map(|&(x, y)| (64 + x, 6 + y)).collect();
+        moved.sort_by_key(|&(x, y)| (y, x));
+        assert_eq!(live_cells(&grid), moved);
+    }
+
+    #[test]
+    fn extreme_coordinates_dont_overflow() {
+        let (lo, hi) = (isize::MIN, isize::MAX);
+        let mut grid = BitGrid::new_empty(70, 10);
+        assert!(!grid.toggle(lo, hi));
+        grid.set_line(lo, lo, hi, hi, true);
+        grid.set_line(hi, hi, lo, lo, true);
+        let pattern = Pattern {
+            width: usize::MAX,
+            height: usize::MAX,
+            cells: vec![(0, 0), (usize::MAX, usize::MAX)],
+        };
+        grid.place_pattern(&pattern, hi, hi);
+        grid.place_pattern(&pattern, lo, lo);
+        grid.place_pattern(&pattern, 0, 0);
+        grid.update();
+    }
+
+    #[test]
+    fn random_soup_matches_naive_life() {
+        let mut grid = BitGrid::new_empty(70, 30);
+        grid.reseed((3, 4));
+        grid.randomize();
+        for _ in 0..10 {
+            let expected = naive_step(&grid);
+            grid.update();
+            assert_eq!(live_cells(&grid), expected);
+        }
+    }
+
+    #[test]
+    fn glider_from_particles_matches_naive_life() {
+        // Life mode starts from whatever particles are on the sand grid.
+        let mut sand = crate::SandGrid::new_empty(20, 20);
+        for &(x, y) in &GLIDER {
+            sand.set(x as isize + 3, y as isize + 3, crate::Particle::new(crate::MAGNET, false));
+        }
+        let mut grid = sand.to_life();
+        let mut expected: Vec<_> = GLIDER.iter().map(|&(x, y)| (x + 3, y + 3)).collect();
+        expected.sort_by_key(|&(x, y)| (y, x));
+        assert_eq!(live_cells(&grid), expected);
+        for generation in 1..=8 {
+            let expected = naive_step(&grid);
+            grid.update();
+            assert_eq!(live_cells(&grid), expected, "generation {}", generation);
+        }
+    }
+
+    #[test]
+    fn neighbor_colors_go_blue_green_red() {
+        for count in 0..=8 {
+            let [r, g, b, a] = neighbor_color(count);
+            assert_eq!(a, 0xff);
+            match count {
+                0 | 1 => assert!(b > g && b > r, "{}", count),
+                2 | 3 => assert!(g > b && g >= r, "{}", count),
+                4..=7 => assert!(r == 0xff && r > g && r > b, "{}", count),
+                _ => assert_eq!([r, g, b], [0xff; 3]),
+            }
+        }
+        // Each count gets its own colour, and anything past 8 is as crowded
+        // as it gets.
+        for count in 1..=8 {
+            assert_ne!(neighbor_color(count), neighbor_color(count - 1));
+        }
+        assert_eq!(neighbor_color(100), neighbor_color(8));
+        assert_eq!(neighbor_color(2), [0x20, 0xe0, 0x60, 0xff]);
+    }
+
+    #[test]
+    fn reversible_steps_back_to_the_start() {
+        let mut grid = BitGrid::new_empty(70, 40);
+        grid.reseed((1, 2));
+        grid.set_rules(Rules::Reversible);
+        grid.randomize();
+        let start = live_cells(&grid);
+        for _ in 0..10 {
+            grid.update();
+        }
+        assert_ne!(live_cells(&grid), start);
+        for _ in 0..10 {
+            assert!(grid.step_back());
+        }
+        assert_eq!(live_cells(&grid), start);
+    }
+
+    #[test]
+    fn plain_life_cant_step_back() {
+        let mut grid = BitGrid::new_empty(10, 10);
+        for &(x, y) in GLIDER.iter() {
+            grid.set(x, y, true);
+        }
+        grid.update();
+        let now = live_cells(&grid);
+        assert!(!grid.step_back());
+        assert_eq!(live_cells(&grid), now);
+    }
+}
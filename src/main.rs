@@ -5,45 +5,467 @@ use env_logger::Env;
 use log::{debug, error, info, warn};
 
 use pixels::{Error, Pixels, SurfaceTexture};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use winit::dpi::{LogicalPosition, LogicalSize, PhysicalSize};
-use winit::event::{Event, VirtualKeyCode};
+use winit::event::{Event, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit_input_helper::WinitInputHelper;
-use env_logger::Env;
 
 extern crate lib;
-use crate::lib::SandGrid;
+use crate::lib::{
+    stats_json, Automaton, BitGrid, CameraFollow, FlowRate, Input, Palette, PixelFormat, Rewind,
+    AutoRefill, Breakpoint, Breakpoints, Command, IdleTimer, RefillMode, Rules, SandGrid, Script, ScreenshotScale, Session, StepStats, StepTuner, Viewport,
+};
 
 const SCREEN_WIDTH: u32 = 3;
 const SCREEN_HEIGHT: u32 = 10;
 
+/// Recorded sessions keep a snapshot every this many frames for seeking.
+const KEYFRAME_INTERVAL: u64 = 60;
+/// How far PageUp/PageDown move through a recorded session.
+const SEEK_STEP: u64 = 10;
+/// Frames kept for rewinding by default.
+const REWIND_DEPTH: usize = 120;
+/// Simulated seconds per update when running headless, for flow rates.
+const HEADLESS_STEP_SECS: f64 = 1.0 / 60.0;
+/// How much ;/' change the randomize fill density by.
+const FILL_DENSITY_STEP: f32 = 0.1;
+/// Largest drizzle brush, in cells either side of the stroke.
+const MAX_BRUSH_RADIUS: usize = 8;
+const MAX_ZOOM: u32 = 16;
+/// Settled chunks offscreen are only updated once every this many updates.
+const OFFSCREEN_INTERVAL: u64 = 4;
+/// Default seconds without input before the screensaver kicks in.
+const IDLE_SECS: f64 = 60.0;
+/// Seconds between screensaver randomizes.
+const SCREENSAVER_PERIOD_SECS: f64 = 30.0;
+/// Updates with nothing moving before `--refill` livens the grid up.
+const REFILL_AFTER: u64 = 120;
+/// Frame time budget for simulating when steps per frame are auto-tuned.
+const UPDATE_BUDGET_SECS: f64 = 0.012;
+
+/// Command line options.
+#[derive(Debug, Default)]
+struct Options {
+    /// Check grid invariants after every update (on by default in debug builds).
+    verify: bool,
+    /// Panic on the first invariant violation instead of just logging it.
+    verify_panic: bool,
+    /// Byte order to draw pixels in.
+    pixel_format: PixelFormat,
+    /// Custom material definitions, loaded before anything that names
+    /// materials.
+    material_defs: Option<String>,
+    /// `(material, pattern file)` layers stacked into the starting grid, later
+    /// ones on top.
+    layers: Vec<(String, String)>,
+    /// Reaction table file overriding/extending the built-in reactions.
+    reactions: Option<String>,
+    /// Click when enough grains land at once (needs the `sound` feature).
+    sound: bool,
+    /// Most cells a grain may fall in one update.
+    max_fall: Option<usize>,
+    /// How strongly buried grains are held in place by the pile above.
+    pressure: Option<f32>,
+    /// Comma separated materials the user can draw with (all if unset).
+    palette: Option<String>,
+    /// Frames kept for rewinding (each one a full copy of the grid).
+    rewind_depth: Option<usize>,
+    /// Save the grid here on exit, and resume from it on startup.
+    autosave: Option<String>,
+    /// Run this many updates without a window, then exit.
+    headless: Option<u64>,
+    /// Where a headless run writes its per-update statistics as JSON.
+    stats_out: Option<String>,
+    /// Seed for the grid's PRNG, for repeatable runs.
+    seed: Option<(u64, u64)>,
+    /// `state_hash` a headless run must end on, or it exits with an error.
+    expect_hash: Option<u64>,
+    /// Fewest empty cells randomize leaves between particles.
+    min_spacing: Option<usize>,
+    /// Depth of the border kept empty, away from edge effects.
+    margin: Option<usize>,
+    /// Randomize or rain on the grid once it goes still.
+    refill: Option<RefillMode>,
+    /// Updates with nothing moving before refilling.
+    refill_after: Option<u64>,
+    /// Pixels of the screen buffer each way per cell, for a chunky look.
+    cell_size: Option<u32>,
+    /// Timed commands to run as the grid advances.
+    script: Option<String>,
+    /// Conditions that pause the simulation when they start to hold.
+    breakpoints: Vec<Breakpoint>,
+    /// Randomize and rain on the grid by itself when left alone.
+    screensaver: bool,
+    /// Seconds without input before the screensaver starts.
+    idle_secs: Option<f64>,
+    /// Leave off the window's title bar and borders.
+    no_decorations: bool,
+    /// Keep the window above all others.
+    always_on_top: bool,
+    /// Run a second, differently seeded grid alongside the first.
+    compare: bool,
+}
+
+impl Options {
+    fn parse<I: Iterator<Item = String>>(args: I) -> Self {
+        let mut options = Options {
+            verify: cfg!(debug_assertions),
+            ..Options::default()
+        };
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--verify" => options.verify = true,
+                "--verify=panic" => {
+                    options.verify = true;
+                    options.verify_panic = true;
+                }
+                "--bgra" => options.pixel_format = PixelFormat::Bgra,
+                "--material-defs" => options.material_defs = args.next(),
+                "--layer" => match (args.next(), args.next()) {
+                    (Some(material), Some(path)) => options.layers.push((material, path)),
+                    _ => warn!("--layer needs a material and a pattern file"),
+                },
+                "--reactions" => options.reactions = args.next(),
+                "--sound" => options.sound = true,
+                "--max-fall" => match args.next().and_then(|n| n.parse().ok()) {
+                    Some(n) => options.max_fall = Some(n),
+                    None => warn!("--max-fall needs a number of cells"),
+                },
+                "--pressure" => match args.next().and_then(|n| n.parse().ok()) {
+                    Some(pressure) => options.pressure = Some(pressure),
+                    None => warn!("--pressure needs a number"),
+                },
+                "--materials" => match args.next() {
+                    Some(list) => options.palette = Some(list),
+                    None => warn!("--materials needs a list like sand,magnet"),
+                },
+                "--rewind" => match args.next().and_then(|n| n.parse().ok()) {
+                    Some(depth) => options.rewind_depth = Some(depth),
+                    None => warn!("--rewind needs a number of frames"),
+                },
+                "--headless" => match args.next().and_then(|n| n.parse().ok()) {
+                    Some(steps) => options.headless = Some(steps),
+                    None => warn!("--headless needs a number of updates"),
+                },
+                "--stats-out" => options.stats_out = args.next(),
+                "--seed" => match (args.next().and_then(|a| a.parse().ok()), args.next().and_then(|b| b.parse().ok())) {
+                    (Some(a), Some(b)) => options.seed = Some((a, b)),
+                    _ => warn!("--seed needs two numbers"),
+                },
+                "--expect-hash" => match args.next().and_then(|h| u64::from_str_radix(h.trim_start_matches("0x"), 16).ok()) {
+                    Some(hash) => options.expect_hash = Some(hash),
+                    None => warn!("--expect-hash needs a hex hash"),
+                },
+                "--min-spacing" => match args.next().and_then(|n| n.parse().ok()) {
+                    Some(spacing) => options.min_spacing = Some(spacing),
+                    None => warn!("--min-spacing needs a number of cells"),
+                },
+                "--margin" => match args.next().and_then(|n| n.parse().ok()) {
+                    Some(margin) => options.margin = Some(margin),
+                    None => warn!("--margin needs a number of cells"),
+                },
+                "--refill" => match args.next().as_deref().and_then(RefillMode::parse) {
+                    Some(mode) => options.refill = Some(mode),
+                    None => warn!("--refill needs randomize or rain"),
+                },
+                "--refill-after" => match args.next().and_then(|n| n.parse().ok()).filter(|&n| n > 0) {
+                    Some(updates) => options.refill_after = Some(updates),
+                    None => warn!("--refill-after needs a number of updates"),
+                },
+                "--cell-size" => match args.next().and_then(|n| n.parse().ok()).filter(|&n| n > 0) {
+                    Some(size) => options.cell_size = Some(size),
+                    None => warn!("--cell-size needs a number of pixels"),
+                },
+                "--autosave" => options.autosave = args.next(),
+                "--script" => options.script = args.next(),
+                "--break" => match args.next().as_deref().and_then(Breakpoint::parse) {
+                    Some(breakpoint) => options.breakpoints.push(breakpoint),
+                    None => warn!("--break needs a condition like active>100, cell=10,20 or floating"),
+                },
+                "--screensaver" => options.screensaver = true,
+                "--idle-secs" => match args.next().and_then(|n| n.parse().ok()).filter(|&n: &f64| n >= 0.0) {
+                    Some(secs) => options.idle_secs = Some(secs),
+                    None => warn!("--idle-secs needs a number of seconds"),
+                },
+                "--no-decorations" => options.no_decorations = true,
+                "--always-on-top" => options.always_on_top = true,
+                "--compare" => options.compare = true,
+                other => warn!("Ignoring unknown argument {:?}", other),
+            }
+        }
+        options
+    }
+
+    fn cell_size(&self) -> u32 {
+        self.cell_size.unwrap_or(1)
+    }
+
+    /// Size of the screen buffer: one grid, or two side by side.
+    fn buffer_size(&self) -> PhysicalSize<u32> {
+        let panes = if self.compare { 2 } else { 1 };
+        PhysicalSize::new(
+            SCREEN_WIDTH * self.cell_size() * panes,
+            SCREEN_HEIGHT * self.cell_size(),
+        )
+    }
+}
 
 fn main() -> Result<(), Error> {
     env_logger::Builder::from_env(
         Env::default().default_filter_or("error,conways_gos=info"),
     )
     .init();
+    let options = Options::parse(std::env::args().skip(1));
     log::info!("World dimensions: {}x{}", SCREEN_WIDTH, SCREEN_HEIGHT);
 
+    //let mut frame = SandGrid::new_random(SCREEN_WIDTH as usize, SCREEN_HEIGHT as usize);
+    let mut frame = options
+        .autosave
+        .as_deref()
+        .and_then(load_autosave)
+        .unwrap_or_else(|| SandGrid::new_empty(SCREEN_WIDTH as usize, SCREEN_HEIGHT as usize));
+    frame.set_pixel_format(options.pixel_format);
+    if let Some(seed) = options.seed {
+        frame.reseed(seed);
+    }
+    if let Some(path) = &options.material_defs {
+        let loaded = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| frame.materials_mut().load_str(&text).map_err(|e| e.to_string()));
+        if let Err(e) = loaded {
+            error!("Couldn't load materials from {}: {}", path, e);
+        }
+        // let the new materials sink like the built-in ones
+        let reactions = lib::ReactionTable::for_materials(frame.materials());
+        *frame.reactions_mut() = reactions;
+    }
+    let materials = frame.materials().clone();
+    let palette = match options.palette.as_deref().map(|list| Palette::parse_with(list, &materials)) {
+        Some(Ok(palette)) => palette,
+        Some(Err(e)) => {
+            warn!("Bad --materials list: {}", e);
+            Palette::all_of(&materials)
+        }
+        None => Palette::all_of(&materials),
+    };
+    frame.set_brush_type(palette.get(0).unwrap_or(lib::SAND));
+    if let Some(max_fall) = options.max_fall {
+        frame.set_max_fall(max_fall);
+    }
+    if let Some(pressure) = options.pressure {
+        frame.set_pressure(pressure);
+    }
+    frame.set_offscreen_interval(OFFSCREEN_INTERVAL);
+    if let Some(spacing) = options.min_spacing {
+        frame.set_min_spacing(spacing);
+    }
+    if let Some(margin) = options.margin {
+        frame.set_margin(margin);
+    }
+    if let Some(path) = &options.reactions {
+        let loaded = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| {
+                let reactions = frame.reactions_mut();
+                reactions.load_str_with(&text, &materials).map_err(|e| e.to_string())
+            });
+        if let Err(e) = loaded {
+            error!("Couldn't load reactions from {}: {}", path, e);
+        }
+    }
+    if options.sound {
+        enable_sound(&mut frame);
+    }
+    for (material, path) in &options.layers {
+        let p_type = match materials.by_name(material) {
+            Some(p_type) => p_type,
+            None => {
+                warn!("Unknown material {:?} for layer {:?}", material, path);
+                continue;
+            }
+        };
+        match load_layer(&frame, p_type, path) {
+            Ok(layer) => frame.merge_layer(&layer),
+            Err(e) => error!("Couldn't load layer {}: {}", path, e),
+        }
+    }
+    let mut script = options.script.as_ref().and_then(|path| {
+        let loaded = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| Script::parse(&text, &materials).map_err(|e| e.to_string()));
+        match loaded {
+            Ok(script) => {
+                info!("Loaded {} script commands from {}", script.len(), path);
+                Some(script)
+            }
+            Err(e) => {
+                error!("Couldn't load script {}: {}", path, e);
+                None
+            }
+        }
+    });
+    // How many times the grid has advanced, to time script commands by.
+    let mut advances: u64 = 0;
+    if let Some(steps) = options.headless {
+        let hash = run_headless(frame, steps, options.stats_out.as_deref(), script);
+        if let Some(expected) = options.expect_hash.filter(|&expected| expected != hash) {
+            error!("Ended on state hash {:016x}, expected {:016x}", hash, expected);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if options.stats_out.is_some() {
+        warn!("--stats-out only applies with --headless");
+    }
+    if options.expect_hash.is_some() {
+        warn!("--expect-hash only applies with --headless");
+    }
+
+    let cell_size = options.cell_size();
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
-    let (window, p_width, p_height, mut _hidpi_factor) =
-        create_window("Conway's Game of Sand", &event_loop);
+    let (window, p_width, p_height, mut hidpi_factor) =
+        create_window("Conway's Game of Sand", &event_loop, &options);
+    // Window size in logical units, which stays put when the scale factor
+    // changes (e.g. dragging the window to another monitor).
+    let mut window_size: LogicalSize<f64> =
+        PhysicalSize::new(p_width, p_height).to_logical(hidpi_factor);
 
     let surface_texture = SurfaceTexture::new(p_width, p_height, &window);
 
-    //let mut frame = SandGrid::new_random(SCREEN_WIDTH as usize, SCREEN_HEIGHT as usize);
-    let mut frame = SandGrid::new_empty(SCREEN_WIDTH as usize, SCREEN_HEIGHT as usize);
-    let mut pixels = Pixels::new(SCREEN_WIDTH, SCREEN_HEIGHT, surface_texture)?;
+    let buffer_size = options.buffer_size();
+    let mut pixels = Pixels::new(buffer_size.width, buffer_size.height, surface_texture)?;
     let mut paused = false;
+    // When set, we're playing plain Life on a bit-packed grid instead of sand.
+    let mut life: Option<BitGrid> = None;
+    let mut life_neighbor_colors = false;
 
+    // Tracks an in-progress mouse stroke. Anything that replaces the grid
+    // contents (or takes focus away mid-drag) must reset this to `None`, or
+    // the rest of the stroke ends up applied to a grid it didn't start on.
     let mut draw_state: Option<bool> = None;
+    // Drizzle mode: holding the mouse keeps pouring grains along the stroke
+    // instead of drawing it, `brush_radius` cells either side of it.
+    let mut emitting = false;
+    // Corners of the region picked by right-dragging, and where a drag began.
+    let mut selection: Option<((isize, isize), (isize, isize))> = None;
+    let mut select_start: Option<(isize, isize)> = None;
+    // The cell the arrow keys shove around by hand, in manual move mode.
+    let mut nudging: Option<(isize, isize)> = None;
+    let mut brush_radius = 0;
+
+    let start = Instant::now();
+    // Heat trails cool by real time, so they fade at the same pace however
+    // many updates a frame runs.
+    let mut last_frame = start;
+    let mut flow_rate = FlowRate::new(1.0);
+    let mut title = String::new();
+    let mut inspecting = false;
+
+    // The session being recorded, if any, and the last finished one (which
+    // PageUp/PageDown scrub through).
+    let mut recording: Option<Session> = None;
+    let mut recorded: Option<Session> = None;
+    let mut seek_frame = 0;
+    // The last few frames, which Left/Right scrub through.
+    let mut rewind = Rewind::new(options.rewind_depth.unwrap_or(REWIND_DEPTH));
+    rewind.push(&frame);
+
+    // Zoom/pan. The grid is drawn at full size into `full_frame`, then the
+    // visible part is scaled up into the pixels buffer.
+    let (grid_w, grid_h) = (SCREEN_WIDTH as usize, SCREEN_HEIGHT as usize);
+    let mut view = Viewport {
+        cell_size,
+        ..Viewport::default()
+    };
+    let mut camera = CameraFollow::default();
+    let mut full_frame = vec![0; 4 * grid_w * grid_h];
+
+    // A/B comparison: a second grid drawn beside the first and stepped
+    // along with it. Edits go to the focused half, which is always `frame`;
+    // clicking the other half swaps the two (and their rewind frames).
+    let mut other = options.compare.then(|| {
+        let mut other = frame.clone();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        other.reseed((nanos, !nanos));
+        other
+    });
+    let mut other_rewind = Rewind::new(rewind.depth());
+    if let Some(grid) = other.as_ref() {
+        other_rewind.push(grid);
+    }
+    let mut focus_right = false;
+    let pane_width = (SCREEN_WIDTH * cell_size) as usize;
+    let mut pane_frame = vec![0; 4 * pane_width * (SCREEN_HEIGHT * cell_size) as usize];
+
+    // Updates per frame, optionally tuned to keep frames fast.
+    let mut steps_per_frame = 1;
+    let mut tuner: Option<StepTuner> = None;
+
+    let mut breakpoints = Breakpoints::new();
+    for &breakpoint in &options.breakpoints {
+        breakpoints.add(breakpoint);
+    }
+    let mut refill = options
+        .refill
+        .map(|mode| AutoRefill::new(mode, options.refill_after.unwrap_or(REFILL_AFTER)));
+
+    let mut idle = options
+        .screensaver
+        .then(|| IdleTimer::new(options.idle_secs.unwrap_or(IDLE_SECS), SCREENSAVER_PERIOD_SECS));
 
     event_loop.run(move |event, _, control_flow| {
+        // Any key or mouse activity hands control back from the screensaver.
+        if let Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput { .. }
+                | WindowEvent::MouseInput { .. }
+                | WindowEvent::MouseWheel { .. }
+                | WindowEvent::CursorMoved { .. },
+            ..
+        } = event
+        {
+            if let Some(idle) = idle.as_mut() {
+                idle.input(start.elapsed().as_secs_f64());
+            }
+        }
+
+        if loses_focus(&event) {
+            debug!("Focus lost, dropping draw state");
+            draw_state = None;
+        }
+
+        // However we're exiting, this comes last.
+        if let Event::LoopDestroyed = event {
+            if let Some(path) = options.autosave.as_deref() {
+                // Always the left grid, whichever is being edited.
+                let primary = other.as_ref().filter(|_| focus_right).unwrap_or(&frame);
+                autosave(primary, path);
+            }
+            return;
+        }
+
         // The one and only event that winit_input_helper doesn't have for us...
         if let Event::RedrawRequested(_) = event {
-            frame.draw(pixels.get_frame());
+            current(&mut frame, &mut life).draw(&mut full_frame);
+            match other.as_ref() {
+                Some(grid) => {
+                    let screen_width = buffer_size.width as usize;
+                    let focused = focus_right as usize;
+                    view.blit(&full_frame, &mut pane_frame, grid_w, grid_h);
+                    lib::blit_pane(&pane_frame, pane_width, pixels.get_frame(), screen_width, focused);
+                    grid.draw(&mut full_frame);
+                    view.blit(&full_frame, &mut pane_frame, grid_w, grid_h);
+                    lib::blit_pane(&pane_frame, pane_width, pixels.get_frame(), screen_width, 1 - focused);
+                }
+                None => view.blit(&full_frame, pixels.get_frame(), grid_w, grid_h),
+            }
             if pixels
                 .render()
                 .map_err(|e| error!("pixels.render() failed: {}", e))
@@ -57,11 +479,32 @@ fn main() -> Result<(), Error> {
         // For everything else, for let winit_input_helper collect events to build its state.
         // It returns `true` when it is time to update our game state and request a redraw.
         if input.update(&event) {
+            let now = Instant::now();
+            let dt = now.duration_since(last_frame).as_secs_f32();
+            last_frame = now;
             // Close events
             if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
                 *control_flow = ControlFlow::Exit;
                 return;
             }
+            draw_state = stroke_after_keys(draw_state, |key| input.key_pressed(key));
+            if let Some(idle) = idle.as_mut().filter(|_| life.is_none()) {
+                let elapsed = start.elapsed().as_secs_f64();
+                if idle.poll(elapsed) {
+                    debug!("Idle, screensaver randomizing");
+                    let warmup = lib::DEFAULT_WARMUP_UPDATES;
+                    edit(&mut frame, &mut recording, Input::Randomize { warmup });
+                    paused = false;
+                }
+                if idle.is_idle(elapsed) {
+                    // Rain the brush material from a spot sweeping back and
+                    // forth along the top.
+                    let x = (((elapsed * 0.3).sin() * 0.5 + 0.5) * (grid_w - 1) as f64) as isize;
+                    let y = frame.margin() as isize;
+                    let rain = Input::Emit { x0: x, y0: y, x1: x, y1: y, radius: 1 };
+                    edit(&mut frame, &mut recording, rain);
+                }
+            }
             if input.key_pressed(VirtualKeyCode::P) {
                 paused = !paused;
             }
@@ -69,21 +512,254 @@ fn main() -> Result<(), Error> {
                 // Space is frame-step, so ensure we're paused
                 paused = true;
             }
+            if input.key_pressed(VirtualKeyCode::S) && life.is_none() {
+                if recording.is_some() {
+                    // Sessions record every update, which would defeat the point.
+                    warn!("Can't settle while recording");
+                } else {
+                    let steps = frame.settle();
+                    info!("Settled in {} updates", steps);
+                    rewind.push(&frame);
+                }
+            }
             if input.key_pressed(VirtualKeyCode::R) {
-                frame.randomize();
+                if let Some(life) = life.as_mut() {
+                    life.randomize();
+                } else if let Some(((x0, y0), (x1, y1))) = selection {
+                    // Just the selected region; shift keeps walls in place.
+                    let fill = frame.fill_density();
+                    let keep_walls = input.held_shift();
+                    let region = Input::RandomizeRegion { x0, y0, x1, y1, fill, keep_walls };
+                    edit(&mut frame, &mut recording, region);
+                } else {
+                    // Warm up across frames rather than blocking on a big grid.
+                    let warmup = lib::DEFAULT_WARMUP_UPDATES;
+                    edit(&mut frame, &mut recording, Input::Randomize { warmup });
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::W) {
+                // "Wet" sand clumps together instead of spreading flat.
+                let cohesion = if frame.cohesion() > 0.0 { 0.0 } else { 0.8 };
+                edit(&mut frame, &mut recording, Input::Cohesion(cohesion));
+            }
+            let fill_step = if input.key_pressed(VirtualKeyCode::Apostrophe) {
+                FILL_DENSITY_STEP
+            } else if input.key_pressed(VirtualKeyCode::Semicolon) {
+                -FILL_DENSITY_STEP
+            } else {
+                0.0
+            };
+            if fill_step != 0.0 {
+                let density = frame.fill_density() + fill_step;
+                edit(&mut frame, &mut recording, Input::FillDensity(density));
+            }
+            if input.key_pressed(VirtualKeyCode::V) && !input.held_control() {
+                frame.set_speed_tint(!frame.speed_tint());
+            }
+            if input.key_pressed(VirtualKeyCode::G) {
+                frame.set_show_heat(!frame.show_heat());
+            }
+            if input.key_pressed(VirtualKeyCode::O) {
+                frame.set_show_ghost(!frame.show_ghost());
+            }
+            if input.key_pressed(VirtualKeyCode::Equals) {
+                view.set_zoom((view.zoom * 2).min(MAX_ZOOM), grid_w, grid_h);
+            }
+            if input.key_pressed(VirtualKeyCode::Minus) {
+                view.set_zoom(view.zoom / 2, grid_w, grid_h);
+            }
+            if input.key_pressed(VirtualKeyCode::F) {
+                camera.enabled = !camera.enabled;
+            }
+            if input.held_shift() {
+                // Manual panning takes over from the follow camera.
+                let step = 1.0_f32.max(view.visible_size(grid_w, grid_h).0 / 8.0);
+                let pans = [
+                    (VirtualKeyCode::Left, (-step, 0.0)),
+                    (VirtualKeyCode::Right, (step, 0.0)),
+                    (VirtualKeyCode::Up, (0.0, -step)),
+                    (VirtualKeyCode::Down, (0.0, step)),
+                ];
+                for &(key, (dx, dy)) in pans.iter() {
+                    if input.key_pressed(key) {
+                        camera.enabled = false;
+                        view.x += dx;
+                        view.y += dy;
+                        view.clamp(grid_w, grid_h);
+                    }
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::H) {
+                if input.held_shift() {
+                    frame.reset_accumulation();
+                } else {
+                    frame.set_show_accumulation(!frame.show_accumulation());
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::T) {
+                tuner = match tuner {
+                    Some(_) => None,
+                    None => Some(StepTuner::new(UPDATE_BUDGET_SECS)),
+                };
+            }
+            if input.key_pressed(VirtualKeyCode::LBracket) {
+                tuner = None;
+                steps_per_frame = (steps_per_frame - 1).max(1);
+            }
+            if input.key_pressed(VirtualKeyCode::RBracket) {
+                tuner = None;
+                steps_per_frame += 1;
+            }
+            if input.key_pressed(VirtualKeyCode::L) {
+                // Life starts from whatever sand is on screen; leaving it goes
+                // back to the sand grid as it was.
+                life = match life {
+                    Some(_) => None,
+                    None => {
+                        let mut grid = frame.to_life();
+                        grid.set_pixel_format(options.pixel_format);
+                        grid.set_color_by_neighbors(life_neighbor_colors);
+                        Some(grid)
+                    }
+                };
+            }
+            if input.key_pressed(VirtualKeyCode::K) {
+                if let Some(life) = life.as_mut() {
+                    let rules = match life.rules() {
+                        Rules::Life => Rules::Reversible,
+                        Rules::Reversible => Rules::Life,
+                    };
+                    life.set_rules(rules);
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::N) {
+                life_neighbor_colors = !life_neighbor_colors;
+                if let Some(life) = life.as_mut() {
+                    life.set_color_by_neighbors(life_neighbor_colors);
+                }
+            }
+            // Number keys pick from the palette in order; Tab cycles it.
+            let slots = [
+                VirtualKeyCode::Key1,
+                VirtualKeyCode::Key2,
+                VirtualKeyCode::Key3,
+                VirtualKeyCode::Key4,
+                VirtualKeyCode::Key5,
+                VirtualKeyCode::Key6,
+                VirtualKeyCode::Key7,
+                VirtualKeyCode::Key8,
+                VirtualKeyCode::Key9,
+            ];
+            for (slot, &key) in slots.iter().enumerate() {
+                if input.key_pressed(key) {
+                    if let Some(p_type) = palette.get(slot) {
+                        edit(&mut frame, &mut recording, Input::Brush(p_type));
+                    }
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::Tab) {
+                let p_type = palette.next(frame.brush_type());
+                edit(&mut frame, &mut recording, Input::Brush(p_type));
+            }
+            if input.key_pressed(VirtualKeyCode::F9) {
+                if let Some(session) = recording.take() {
+                    info!("Recorded {} frames", session.frames());
+                    seek_frame = session.frames();
+                    recorded = Some(session);
+                } else {
+                    info!("Recording");
+                    recording = Some(Session::start(&frame, KEYFRAME_INTERVAL));
+                }
+            }
+            if let Some(session) = recorded.as_ref() {
+                let back = input.key_pressed(VirtualKeyCode::PageUp);
+                let forward = input.key_pressed(VirtualKeyCode::PageDown);
+                if (back || forward) && recording.is_none() && life.is_none() {
+                    seek_frame = if back {
+                        seek_frame.saturating_sub(SEEK_STEP)
+                    } else {
+                        (seek_frame + SEEK_STEP).min(session.frames())
+                    };
+                    debug!("Seek to frame {}", seek_frame);
+                    frame = session.seek(seek_frame);
+                    paused = true;
+                    draw_state = None;
+                }
+            }
+            if let Some(life) = life.as_mut() {
+                // Reversible Life can run backwards exactly.
+                if input.key_pressed(VirtualKeyCode::Left) && life.step_back() {
+                    paused = true;
+                }
             }
-            if input.key_pressed(VirtualKeyCode::Key1) {
-                frame.set_brush_type(1)
+            if !input.held_shift() && life.is_none() && nudging.is_none() {
+                let back = input.key_pressed(VirtualKeyCode::Left);
+                let forward = input.key_pressed(VirtualKeyCode::Right);
+                let snapshot = if (back || forward) && recording.is_some() {
+                    // The session would carry on from a frame it never saw.
+                    warn!("Can't rewind while recording");
+                    None
+                } else if back {
+                    rewind.back(1).cloned()
+                } else if forward {
+                    rewind.forward(1).cloned()
+                } else {
+                    None
+                };
+                if let Some(snapshot) = snapshot {
+                    // Unpausing carries on from whichever frame we're on.
+                    debug!("Rewound to {} frames ago", rewind.position());
+                    frame = snapshot;
+                    paused = true;
+                    draw_state = None;
+                }
             }
-            if input.key_pressed(VirtualKeyCode::Key2) {
-                frame.set_brush_type(2)
+            if input.key_pressed(VirtualKeyCode::F12) {
+                // Shift saves at window size, ctrl a half-size thumbnail,
+                // otherwise one pixel per cell.
+                let scale = if input.held_shift() {
+                    let size = window.inner_size();
+                    ScreenshotScale::Scaled {
+                        width: size.width,
+                        height: size.height,
+                    }
+                } else if input.held_control() {
+                    ScreenshotScale::Scaled {
+                        width: SCREEN_WIDTH / 2,
+                        height: SCREEN_HEIGHT / 2,
+                    }
+                } else {
+                    ScreenshotScale::Native
+                };
+                let stamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis())
+                    .unwrap_or_default();
+                let path = format!("screenshot-{}.png", stamp);
+                match lib::save_screenshot(&frame, &path, scale) {
+                    Ok(()) => info!("Saved {}", path),
+                    Err(e) => error!("Saving {} failed: {}", path, e),
+                }
+            }
+            if life.is_none() {
+                if input.key_pressed(VirtualKeyCode::X) {
+                    edit(&mut frame, &mut recording, Input::Flip { horizontal: true });
+                }
+                if input.key_pressed(VirtualKeyCode::Y) {
+                    edit(&mut frame, &mut recording, Input::Flip { horizontal: false });
+                }
             }
             if input.key_pressed(VirtualKeyCode::C) {
-                frame.clear();
+                match life.as_mut() {
+                    Some(life) => life.clear(),
+                    None => {
+                        edit(&mut frame, &mut recording, Input::Clear);
+                    }
+                }
             }
             // Handle mouse. This is a bit involved since support some simple
             // line drawing (mostly because it makes nice looking patterns).
-            let (mouse_cell, mouse_prev_cell) = input
+            let (mouse_pane, mouse_cell, mouse_prev_cell) = input
                 .mouse()
                 .map(|(mx, my)| {
                     let (dx, dy) = input.mouse_diff();
@@ -98,16 +774,121 @@ fn main() -> Result<(), Error> {
                         .window_pos_to_pixel((prev_x, prev_y))
                         .unwrap_or_else(|pos| pixels.clamp_pixel_pos(pos));
 
+                    // Side by side, each half maps onto its own grid.
+                    let (pane, mx_i) = lib::split_pane(mx_i, pane_width);
+                    let (_, px_i) = lib::split_pane(px_i, pane_width);
                     (
-                        (mx_i as isize, my_i as isize),
-                        (px_i as isize, py_i as isize),
+                        pane,
+                        view.screen_to_cell((mx_i as isize, my_i as isize)),
+                        view.screen_to_cell((px_i as isize, py_i as isize)),
                     )
                 })
                 .unwrap_or_default();
+            let over_other = other.is_some() && (mouse_pane == 1) != focus_right;
+
+            if input.key_pressed(VirtualKeyCode::M) && life.is_none() {
+                nudging = match nudging {
+                    Some(_) => None,
+                    None => frame.get(mouse_cell.0, mouse_cell.1).map(|_| mouse_cell),
+                };
+            }
+            if let Some((x, y)) = nudging.filter(|_| !input.held_shift()) {
+                let nudges = [
+                    (VirtualKeyCode::Left, (-1, 0)),
+                    (VirtualKeyCode::Right, (1, 0)),
+                    (VirtualKeyCode::Up, (0, -1)),
+                    (VirtualKeyCode::Down, (0, 1)),
+                ];
+                for &(key, (dx, dy)) in nudges.iter() {
+                    if input.key_pressed(key) && edit(&mut frame, &mut recording, Input::Nudge { x, y, dx, dy }) {
+                        nudging = Some((x + dx, y + dy));
+                    }
+                }
+            }
+
+            if input.key_pressed(VirtualKeyCode::E) {
+                // Eyedropper: draw with whatever is under the cursor
+                let picked = frame.pick(mouse_cell.0, mouse_cell.1);
+                if let Some(p_type) = picked.filter(|&p| palette.allows(p)) {
+                    edit(&mut frame, &mut recording, Input::Brush(p_type));
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::I) {
+                inspecting = !inspecting;
+            }
+            if input.key_pressed(VirtualKeyCode::B) && life.is_none() {
+                // Pause when something moves into the cell under the mouse.
+                let (x, y) = mouse_cell;
+                if frame.get(x, y).is_some() {
+                    let breakpoint = Breakpoint::CellActive(x as usize, y as usize);
+                    if breakpoints.contains(breakpoint) {
+                        breakpoints.remove(breakpoint);
+                    } else {
+                        breakpoints.add(breakpoint);
+                    }
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::D) {
+                if input.held_shift() {
+                    let rainbow = !frame.rainbow_emit();
+                    edit(&mut frame, &mut recording, Input::RainbowEmit(rainbow));
+                } else {
+                    emitting = !emitting;
+                    draw_state = None;
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::Period) {
+                brush_radius = (brush_radius + 1).min(MAX_BRUSH_RADIUS);
+            }
+            if input.key_pressed(VirtualKeyCode::Comma) {
+                brush_radius = brush_radius.saturating_sub(1);
+            }
+            if input.key_pressed(VirtualKeyCode::V) && input.held_control() && input.held_shift() {
+                match paste_pattern() {
+                    Ok(pattern) => {
+                        let (x, y) = mouse_cell;
+                        match life.as_mut() {
+                            Some(life) => life.place_pattern(&pattern, x, y),
+                            None => {
+                                edit(&mut frame, &mut recording, Input::Pattern { pattern, x, y });
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Couldn't paste a pattern: {}", e),
+                }
+            }
 
-            if input.mouse_pressed(0) {
+            let mouse_down = input.mouse_pressed(0) || input.mouse_held(0);
+            if over_other && input.mouse_pressed(0) {
+                // Clicking the other half of an A/B comparison only switches
+                // to it. Not while recording: a session only follows one grid.
+                if recording.is_some() {
+                    warn!("Can't switch grids while recording");
+                } else if let Some(grid) = other.as_mut().filter(|_| select_start.is_none()) {
+                    std::mem::swap(&mut frame, grid);
+                    std::mem::swap(&mut rewind, &mut other_rewind);
+                    focus_right = !focus_right;
+                    selection = None;
+                    draw_state = None;
+                }
+            } else if emitting && life.is_none() && mouse_down && !input.held_shift() {
+                // Pour along this frame's stretch of the stroke, every frame
+                // the button is down, whether or not the mouse moved.
+                let ((x0, y0), (x1, y1)) = (mouse_prev_cell, mouse_cell);
+                let emit = Input::Emit { x0, y0, x1, y1, radius: brush_radius };
+                edit(&mut frame, &mut recording, emit);
+            } else if input.mouse_pressed(0) && input.held_shift() {
+                // Shift-click places the flow measurement line instead of drawing
+                debug!("Flow row set to {}", mouse_cell.1);
+                frame.set_flow_row(Some(mouse_cell.1 as usize));
+                flow_rate.reset();
+            } else if input.mouse_pressed(0) {
                 debug!("Mouse click at {:?}", mouse_cell);
-                draw_state = Some(frame.toggle(mouse_cell.0, mouse_cell.1));
+                let (x, y) = mouse_cell;
+                draw_state = Some(match life.as_mut() {
+                    Some(life) => life.toggle(x, y),
+                    None => edit(&mut frame, &mut recording, Input::Toggle { x, y }),
+                });
             } else if let Some(draw_alive) = draw_state {
                 let release = input.mouse_released(0);
                 let held = input.mouse_held(0);
@@ -117,13 +898,14 @@ fn main() -> Result<(), Error> {
                 // in the middle of drawing, keep going.
                 if release || held {
                     debug!("Draw line of {:?}", draw_alive);
-                    frame.set_line(
-                        mouse_prev_cell.0,
-                        mouse_prev_cell.1,
-                        mouse_cell.0,
-                        mouse_cell.1,
-                        draw_alive,
-                    );
+                    let ((x0, y0), (x1, y1)) = (mouse_prev_cell, mouse_cell);
+                    match life.as_mut() {
+                        Some(life) => life.set_line(x0, y0, x1, y1, draw_alive),
+                        None => {
+                            let line = Input::Line { x0, y0, x1, y1, active: draw_alive };
+                            edit(&mut frame, &mut recording, line);
+                        }
+                    }
                 }
                 // If they let go or are otherwise not clicking anymore, stop drawing.
                 if release || !held {
@@ -131,40 +913,383 @@ fn main() -> Result<(), Error> {
                     draw_state = None;
                 }
             }
-            // Adjust high DPI factor
+            // Right-drag selects a region (for R); a right click clears it.
+            if input.mouse_pressed(1) {
+                select_start = Some(mouse_cell);
+            }
+            if let Some(start) = select_start {
+                if start != mouse_cell {
+                    selection = Some((start, mouse_cell));
+                }
+                if input.mouse_released(1) {
+                    if start == mouse_cell {
+                        selection = None;
+                    }
+                    select_start = None;
+                }
+            }
+            // Adjust high DPI factor: keep the window the same logical size,
+            // so the surface needs resizing to the new physical size.
             if let Some(factor) = input.scale_factor_changed() {
-                _hidpi_factor = factor;
+                debug!("Scale factor {} => {}", hidpi_factor, factor);
+                hidpi_factor = factor;
+                window.set_min_inner_size(Some(min_window_size(factor, buffer_size)));
+                let size = surface_size(window_size, factor, buffer_size);
+                pixels.resize(size.width, size.height);
             }
             // Resize the window
             if let Some(size) = input.window_resized() {
+                window_size = size.to_logical(hidpi_factor);
                 pixels.resize(size.width, size.height);
             }
-            if !paused || input.key_pressed(VirtualKeyCode::Space) {
-                frame.update();
+            // Sand updates this frame, for keeping the other grid in step.
+            let mut updates = 0;
+            if let Some(life) = life.as_mut() {
+                if !paused || input.key_pressed(VirtualKeyCode::Space) {
+                    life.update();
+                }
+            } else if let Some((done, total)) = frame.warmup_progress() {
+                debug!("Randomize warm-up {}/{}", done, total);
+                run_script(&mut script, advances, &mut frame, &mut recording);
+                frame.warmup_step();
+                advances += 1;
+                updates = 1;
+                if let Some(session) = recording.as_mut() {
+                    session.step(&frame);
+                }
+            } else if !paused || input.key_pressed(VirtualKeyCode::Space) {
+                // Zoomed in, settled chunks offscreen can be updated less
+                // often. Not while recording, since the view isn't recorded
+                // and replays have to match.
+                let visible = Some(view.visible_cells(grid_w, grid_h))
+                    .filter(|_| view.zoom > 1 && recording.is_none());
+                frame.set_visible_region(visible);
+                // Frame-stepping always does a single update.
+                let steps = if paused { 1 } else { steps_per_frame };
+                let update_start = Instant::now();
+                for _ in 0..steps {
+                    run_script(&mut script, advances, &mut frame, &mut recording);
+                    advances += 1;
+                    updates += 1;
+                    // Reactions may create or destroy particles, holes
+                    // swallow them and smoke fades; nothing else should.
+                    let before = Some(frame.particle_count()).filter(|_| frame.reactions().is_empty());
+                    frame.update();
+                    if options.verify {
+                        let removed = frame.drained() + frame.dissipated();
+                        let expected = before.map(|n| n - removed as usize);
+                        frame.assert_invariants(expected, options.verify_panic);
+                    }
+                    flow_rate.record(start.elapsed().as_secs_f64(), frame.flow_crossings());
+                    if let Some(session) = recording.as_mut() {
+                        session.step(&frame);
+                    }
+                    if let Some(hit) = breakpoints.check(&frame) {
+                        info!("Breakpoint {} hit at generation {}", hit, frame.generation());
+                        paused = true;
+                        break;
+                    }
+                    if let Some(input) = refill.as_mut().and_then(|refill| refill.check(&frame)) {
+                        debug!("Grid went still, refilling");
+                        edit(&mut frame, &mut recording, input);
+                    }
+                }
+                frame.cool(dt);
+                rewind.push(&frame);
+                if let Some(tuner) = tuner.as_mut() {
+                    let per_update = update_start.elapsed().as_secs_f64() / steps as f64;
+                    steps_per_frame = tuner.record(per_update);
+                }
+            }
+            if let Some(grid) = other.as_mut().filter(|_| updates > 0) {
+                for _ in 0..updates {
+                    lib::advance(grid);
+                }
+                grid.cool(dt);
+                other_rewind.push(grid);
+            }
+            if life.is_none() {
+                camera.step(&mut view, frame.center_of_mass(), grid_w, grid_h);
+            }
+            let mut title_parts = vec!["Conway's Game of Sand".to_string()];
+            if other.is_some() {
+                let side = if focus_right { "right" } else { "left" };
+                title_parts.push(format!("A/B, editing {}", side));
+            }
+            if life.as_ref().is_some_and(|life| life.rules() == Rules::Reversible) {
+                title_parts.push("reversible Life".to_string());
+            }
+            if let Some(row) = frame.flow_row() {
+                title_parts.push(format!(
+                    "row {}: {:.1} grains/s ({} total)",
+                    row,
+                    flow_rate.per_second(),
+                    frame.flow_total()
+                ));
+            }
+            if steps_per_frame != 1 || tuner.is_some() {
+                let mode = if tuner.is_some() { "auto" } else { "manual" };
+                title_parts.push(format!("{} steps/frame ({})", steps_per_frame, mode));
+            }
+            if rewind.position() > 0 {
+                title_parts.push(format!("rewound {} frames", rewind.position()));
+            }
+            if let Some(session) = recording.as_ref() {
+                title_parts.push(format!("recording frame {}", session.frames()));
+            } else if let Some(session) = recorded.as_ref() {
+                title_parts.push(format!("replay {}/{}", seek_frame, session.frames()));
+            }
+            if (frame.fill_density() - lib::DEFAULT_FILL_DENSITY).abs() > f32::EPSILON {
+                title_parts.push(format!("fill {:.0}%", frame.fill_density() * 100.0));
+            }
+            if emitting {
+                title_parts.push(format!("drizzle (radius {})", brush_radius));
+            }
+            if let Some((x, y)) = nudging {
+                title_parts.push(format!("moving ({}, {}) by hand", x, y));
+            }
+            if frame.rainbow_emit() {
+                title_parts.push("rainbow".to_string());
+            }
+            if !breakpoints.is_empty() {
+                let list: Vec<String> = breakpoints.iter().map(|b| b.to_string()).collect();
+                title_parts.push(format!("break on {}", list.join(", ")));
+            }
+            if let Some(((x0, y0), (x1, y1))) = selection {
+                title_parts.push(format!(
+                    "region {}x{}",
+                    (x1 - x0).abs() + 1,
+                    (y1 - y0).abs() + 1
+                ));
+            }
+            if inspecting {
+                if let Some(info) = frame.inspect(mouse_cell.0, mouse_cell.1) {
+                    title_parts.push(info);
+                }
+                let floating = frame.find_floating_grains().len();
+                if floating > 0 {
+                    title_parts.push(format!("{} floating", floating));
+                }
+            }
+            let new_title = title_parts.join(" - ");
+            if new_title != title {
+                window.set_title(&new_title);
+                title = new_title;
             }
             window.request_redraw();
         }
     });
 }
 
+/// Run `steps` updates without a window, collecting statistics after each one
+/// and writing them to `stats_out` (if given) once done. An empty scene is
+/// randomized first (unless there's a `script` to set it up), and the flow
+/// row defaults to halfway down. Returns the grid's `state_hash` at the end.
+fn run_headless(mut frame: SandGrid, steps: u64, stats_out: Option<&str>, mut script: Option<Script>) -> u64 {
+    if frame.particle_count() == 0 && script.is_none() {
+        frame.randomize();
+    }
+    if frame.flow_row().is_none() {
+        frame.set_flow_row(Some(frame.height() / 2));
+    }
+    let mut flow_rate = FlowRate::new(1.0);
+    let mut stats = Vec::with_capacity(steps as usize);
+    for step in 1..=steps {
+        if let Some(script) = script.as_mut() {
+            script.run(step - 1, &mut frame);
+        }
+        lib::advance(&mut frame);
+        flow_rate.record(step as f64 * HEADLESS_STEP_SECS, frame.flow_crossings());
+        stats.push(StepStats::collect(&frame, step, flow_rate.per_second()));
+    }
+    info!("Ran {} updates headless, {} particles left", steps, frame.particle_count());
+    info!("State hash {:016x}", frame.state_hash());
+    if let Some(path) = stats_out {
+        match std::fs::write(path, stats_json(&stats)) {
+            Ok(()) => info!("Wrote stats for {} updates to {}", steps, path),
+            Err(e) => error!("Couldn't write stats to {}: {}", path, e),
+        }
+    }
+    frame.state_hash()
+}
+
+/// Save `frame` to `path` for `--autosave`, logging rather than failing.
+fn autosave(frame: &SandGrid, path: &str) {
+    match frame.save_to_path(path) {
+        Ok(()) => info!("Saved the grid to {}", path),
+        Err(e) => error!("Couldn't save the grid to {}: {}", path, e),
+    }
+}
+
+/// The grid saved at `path` by a previous `--autosave`, if there is one that
+/// fits the window.
+fn load_autosave(path: &str) -> Option<SandGrid> {
+    if !std::path::Path::new(path).exists() {
+        return None;
+    }
+    match SandGrid::load_from_path(path) {
+        Ok(grid) if grid.width() == SCREEN_WIDTH as usize && grid.height() == SCREEN_HEIGHT as usize => {
+            info!("Resuming from {}", path);
+            Some(grid)
+        }
+        Ok(grid) => {
+            warn!(
+                "Ignoring {}: it's {}x{}, not {}x{}",
+                path,
+                grid.width(),
+                grid.height(),
+                SCREEN_WIDTH,
+                SCREEN_HEIGHT
+            );
+            None
+        }
+        Err(e) => {
+            error!("Couldn't load {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Apply an edit to the sand grid, recording it if a session is being
+/// recorded.
+fn edit(frame: &mut SandGrid, recording: &mut Option<Session>, input: Input) -> bool {
+    if let Some(session) = recording {
+        session.record(input.clone());
+    }
+    input.apply(frame)
+}
+
+/// Run the script commands due at `advances`, recording any edits.
+fn run_script(
+    script: &mut Option<Script>,
+    advances: u64,
+    frame: &mut SandGrid,
+    recording: &mut Option<Session>,
+) {
+    let commands = match script.as_mut() {
+        Some(script) => script.due(advances),
+        None => return,
+    };
+    for command in commands {
+        let result = match command {
+            Command::Input(input) => {
+                edit(frame, recording, input);
+                Ok(())
+            }
+            other => other.apply(frame),
+        };
+        if let Err(e) = result {
+            error!("Script command failed: {}", e);
+        }
+    }
+}
+
+/// Grains that have to land in one update to make a click.
+#[cfg(feature = "sound")]
+const CLICK_THRESHOLD: usize = 8;
+/// Minimum seconds between clicks.
+#[cfg(feature = "sound")]
+const CLICK_INTERVAL: f64 = 0.08;
+
+#[cfg(feature = "sound")]
+fn enable_sound(frame: &mut SandGrid) {
+    let player = match lib::ClickPlayer::new() {
+        Ok(player) => player,
+        Err(e) => {
+            error!("No audio output: {}", e);
+            return;
+        }
+    };
+    let start = Instant::now();
+    let mut detector = lib::ImpactDetector::default();
+    let mut trigger = lib::ImpactTrigger::new(CLICK_THRESHOLD, CLICK_INTERVAL);
+    frame.set_update_hook(move |moves| {
+        let landed = detector.landed(moves);
+        if trigger.observe(landed, start.elapsed().as_secs_f64()) {
+            player.click(landed);
+        }
+    });
+}
+
+#[cfg(not(feature = "sound"))]
+fn enable_sound(_frame: &mut SandGrid) {
+    warn!("--sound needs a build with the `sound` feature");
+}
+
+/// A grid the size of `frame` holding the pattern in `path`, centered and
+/// drawn in `p_type`.
+fn load_layer(
+    frame: &SandGrid,
+    p_type: usize,
+    path: &str,
+) -> Result<SandGrid, Box<dyn std::error::Error>> {
+    let pattern = lib::parse_pattern(&std::fs::read_to_string(path)?)?;
+    let mut layer = SandGrid::new_empty(frame.width(), frame.height());
+    layer.set_brush_type(p_type);
+    let (cx, cy) = (frame.width() as isize / 2, frame.height() as isize / 2);
+    layer.place_pattern(&pattern, cx, cy);
+    Ok(layer)
+}
+
+/// Read an ASCII or RLE pattern off the system clipboard.
+fn paste_pattern() -> Result<lib::Pattern, Box<dyn std::error::Error>> {
+    let text = arboard::Clipboard::new()?.get_text()?;
+    Ok(lib::parse_pattern(&text)?)
+}
+
+/// Whichever grid input and rendering should currently go to.
+fn current<'a>(frame: &'a mut SandGrid, life: &'a mut Option<BitGrid>) -> &'a mut dyn Automaton {
+    match life {
+        Some(life) => life,
+        None => frame,
+    }
+}
+
+/// Keys that replace the grid being drawn on: randomize, Life and clear.
+const REPLACES_GRID: [VirtualKeyCode; 3] = [VirtualKeyCode::R, VirtualKeyCode::L, VirtualKeyCode::C];
+
+/// What's left of the in-progress stroke `draw_state` once this frame's
+/// keys are handled. Anything that replaces the grid drops it, or the rest
+/// of the stroke ends up applied to a grid it didn't start on.
+fn stroke_after_keys(draw_state: Option<bool>, pressed: impl Fn(VirtualKeyCode) -> bool) -> Option<bool> {
+    draw_state.filter(|_| !REPLACES_GRID.iter().any(|&key| pressed(key)))
+}
+
+/// Whether `event` takes focus away from the window. winit_input_helper
+/// doesn't tell us about focus changes, and if the button is released while
+/// we're unfocused we'd never see it.
+fn loses_focus(event: &Event<()>) -> bool {
+    matches!(
+        event,
+        Event::WindowEvent {
+            event: WindowEvent::Focused(false),
+            ..
+        }
+    )
+}
+
 // COPYPASTE: ideally this could be shared.
 
 //Methods for managaing meta game processes. Like opening a window or taking user input etc
 fn create_window(
     title: &str,
     event_loop: &EventLoop<()>,
+    options: &Options,
 ) -> (winit::window::Window, u32, u32, f64) {
     // Create a hidden window so we can estimate a good default window size
     let window = winit::window::WindowBuilder::new()
         .with_visible(false)
         .with_title(title)
-        .build(&event_loop)
+        .with_decorations(!options.no_decorations)
+        .with_always_on_top(options.always_on_top)
+        .build(event_loop)
         .unwrap();
     let hidpi_factor = window.scale_factor();
 
     // Get dimensions
-    let width = SCREEN_WIDTH as f64;
-    let height = SCREEN_HEIGHT as f64;
+    let buffer_size = options.buffer_size();
+    let width = buffer_size.width as f64;
+    let height = buffer_size.height as f64;
     let (monitor_width, monitor_height) = {
         if let Some(monitor) = window.current_monitor() {
             let size = monitor.size().to_logical(hidpi_factor);
@@ -173,11 +1298,10 @@ fn create_window(
             (width, height)
         }
     };
-    let scale = (monitor_height / height * 2.0 / 3.0).round().max(1.0);
+    let scale = window_scale((width, height), (monitor_width, monitor_height));
 
     // Resize, center, and display the window
-    let min_size: winit::dpi::LogicalSize<f64> =
-        PhysicalSize::new(width, height).to_logical(hidpi_factor);
+    let min_size = min_window_size(hidpi_factor, buffer_size);
     let default_size = LogicalSize::new(width * scale, height * scale);
     let center = LogicalPosition::new(
         (monitor_width - width * scale) / 2.0,
@@ -188,13 +1312,85 @@ fn create_window(
     window.set_outer_position(center);
     window.set_visible(true);
 
-    let size = default_size.to_physical::<f64>(hidpi_factor);
+    let size = surface_size(default_size, hidpi_factor, buffer_size);
+
+    (window, size.width, size.height, hidpi_factor)
+}
 
-    (
-        window,
-        size.width.round() as u32,
-        size.height.round() as u32,
-        hidpi_factor,
+/// Whole-number scale for a `buffer` sized screen buffer that makes the
+/// window as big as fits in 2/3 of the `monitor` both ways, whichever way
+/// the grid is longer. Never below 1.
+fn window_scale((width, height): (f64, f64), (monitor_width, monitor_height): (f64, f64)) -> f64 {
+    let fit = (monitor_width / width).min(monitor_height / height);
+    (fit * 2.0 / 3.0).floor().max(1.0)
+}
+
+/// Smallest window that still shows one physical pixel per pixel of a
+/// `buffer` sized screen buffer.
+fn min_window_size(scale_factor: f64, buffer: PhysicalSize<u32>) -> LogicalSize<f64> {
+    PhysicalSize::new(buffer.width as f64, buffer.height as f64).to_logical(scale_factor)
+}
+
+/// Physical size of the surface for a window `logical` in size at
+/// `scale_factor`, rounded to whole pixels and never smaller than the buffer.
+fn surface_size(logical: LogicalSize<f64>, scale_factor: f64, buffer: PhysicalSize<u32>) -> PhysicalSize<u32> {
+    let size = logical.to_physical::<f64>(scale_factor);
+    PhysicalSize::new(
+        (size.width.round() as u32).max(buffer.width),
+        (size.height.round() as u32).max(buffer.height),
     )
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replacing_the_grid_drops_the_stroke() {
+        for &key in REPLACES_GRID.iter() {
+            assert_eq!(stroke_after_keys(Some(true), |k| k == key), None);
+        }
+        // Other keys leave it to carry on.
+        assert_eq!(stroke_after_keys(Some(false), |k| k == VirtualKeyCode::G), Some(false));
+        assert_eq!(stroke_after_keys(Some(true), |_| false), Some(true));
+    }
+
+    #[test]
+    fn min_window_size_is_one_pixel_per_buffer_pixel() {
+        let buffer = PhysicalSize::new(300, 100);
+        assert_eq!(min_window_size(1.0, buffer), LogicalSize::new(300.0, 100.0));
+        assert_eq!(min_window_size(2.0, buffer), LogicalSize::new(150.0, 50.0));
+        assert_eq!(min_window_size(1.5, buffer), LogicalSize::new(200.0, 100.0 / 1.5));
+    }
+
+    #[test]
+    fn surface_size_rounds_and_covers_the_buffer() {
+        let buffer = PhysicalSize::new(300, 100);
+        assert_eq!(surface_size(LogicalSize::new(400.3, 200.0), 1.0, buffer), PhysicalSize::new(400, 200));
+        assert_eq!(surface_size(LogicalSize::new(150.3, 60.0), 2.0, buffer), PhysicalSize::new(301, 120));
+        // Never smaller than the buffer, whatever the window says.
+        assert_eq!(surface_size(LogicalSize::new(10.0, 10.0), 1.0, buffer), buffer);
+    }
+
+    #[test]
+    fn window_scale_fits_both_axes() {
+        // Wide: limited by the monitor's width.
+        assert_eq!(window_scale((300.0, 100.0), (1920.0, 1080.0)), 4.0);
+        // Tall: limited by its height, even though the width has room.
+        assert_eq!(window_scale((100.0, 300.0), (1920.0, 1080.0)), 2.0);
+        // Bigger than the monitor still gets one pixel per pixel.
+        assert_eq!(window_scale((3000.0, 2000.0), (1920.0, 1080.0)), 1.0);
+    }
+
+    #[test]
+    fn buffer_size_doubles_for_compare() {
+        let mut options = Options {
+            cell_size: Some(2),
+            ..Options::default()
+        };
+        let single = options.buffer_size();
+        assert_eq!(single, PhysicalSize::new(SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2));
+        options.compare = true;
+        assert_eq!(options.buffer_size(), PhysicalSize::new(single.width * 2, single.height));
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{Gravity, ParseError, DUST, GRAVITY, HOLE, HOT, MAGNET, PARTICLETYPES, SAND, SMOKE, WATER};
+
+/// How a material gets around.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Movement {
+    /// Falls, and slides off diagonally when something's in the way.
+    Powder,
+    /// Falls like a powder, and also spreads sideways.
+    Liquid,
+    /// Stays wherever it's put.
+    Solid,
+    /// Rises, drifting and spreading sideways as it goes.
+    Gas,
+}
+
+impl Movement {
+    pub fn name(self) -> &'static str {
+        match self {
+            Movement::Powder => "powder",
+            Movement::Liquid => "liquid",
+            Movement::Solid => "solid",
+            Movement::Gas => "gas",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        [Movement::Powder, Movement::Liquid, Movement::Solid, Movement::Gas]
+            .iter()
+            .copied()
+            .find(|m| m.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Everything the simulation and renderer need to know about a material.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub name: String,
+    /// `None` draws as background.
+    pub color: Option<[u8; 4]>,
+    /// Falling materials sink through liquids and gases lighter than them.
+    pub density: f32,
+    pub movement: Movement,
+    /// How readily it catches fire, 0.0..=1.0.
+    pub flammability: f32,
+    pub gravity: Gravity,
+    /// Chance per update that a moving particle of it fades away, as smoke
+    /// does. Always 0.0 for materials loaded from a file.
+    pub dissipation: f32,
+    /// Color of the fading trail it leaves in the cells it moves out of
+    /// (shown with the heat trail), or `None` to leave none.
+    pub trail: Option<[u8; 4]>,
+}
+
+/// Trail left by liquids: damp rather than hot.
+pub const DAMP: [u8; 4] = [0x20, 0x50, 0x90, 0xff];
+
+/// The trail a material leaves unless told otherwise: damp for liquids, hot
+/// for anything else that moves, and none for solids.
+fn default_trail(movement: Movement) -> Option<[u8; 4]> {
+    match movement {
+        Movement::Solid => None,
+        Movement::Liquid => Some(DAMP),
+        Movement::Powder | Movement::Gas => Some(HOT),
+    }
+}
+
+/// The materials a grid knows about, indexed by particle type. The built-in
+/// ones always come first, at the indices of their constants (`SAND`, ...).
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialRegistry {
+    materials: Vec<Material>,
+}
+
+impl Default for MaterialRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+/// Chance per update a smoke particle fades away, so puffs last around 60
+/// updates.
+pub const SMOKE_DISSIPATION: f32 = 1.0 / 60.0;
+
+/// Particle types are saved as a byte, so that's as many as there can be.
+pub const MAX_MATERIALS: usize = 256;
+
+impl MaterialRegistry {
+    pub fn builtin() -> Self {
+        let materials = PARTICLETYPES
+            .iter()
+            .zip(GRAVITY.iter())
+            .enumerate()
+            .map(|(p_type, (&name, &gravity))| {
+                let dissipation = if p_type == SMOKE { SMOKE_DISSIPATION } else { 0.0 };
+                let (color, density, movement, flammability) = match p_type {
+                    SAND => (Some([0, 0xff, 0xff, 0xff]), 1.6, Movement::Powder, 0.0),
+                    WATER => (Some([0x20, 0x60, 0xe0, 0xff]), 1.0, Movement::Liquid, 0.0),
+                    MAGNET => (Some([0xc0, 0x20, 0x20, 0xff]), 7.8, Movement::Solid, 0.0),
+                    DUST => (Some([0xa0, 0x90, 0x80, 0xff]), 0.5, Movement::Powder, 0.3),
+                    HOLE => (Some([0x30, 0x10, 0x40, 0xff]), 0.0, Movement::Solid, 0.0),
+                    SMOKE => (Some([0xa0, 0xa0, 0xa0, 0x90]), 0.05, Movement::Gas, 0.0),
+                    // Empty cells.
+                    _ => (None, 1.0, Movement::Solid, 0.0),
+                };
+                let trail = default_trail(movement);
+                Material {
+                    name: name.to_lowercase(),
+                    color,
+                    density,
+                    movement,
+                    flammability,
+                    gravity,
+                    dissipation,
+                    trail,
+                }
+            })
+            .collect();
+        Self { materials }
+    }
+
+    pub fn get(&self, p_type: usize) -> Option<&Material> {
+        self.materials.get(p_type)
+    }
+
+    pub fn get_mut(&mut self, p_type: usize) -> Option<&mut Material> {
+        self.materials.get_mut(p_type)
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Material> {
+        self.materials.iter()
+    }
+
+    /// Look up a particle type by (case-insensitive) name.
+    pub fn by_name(&self, name: &str) -> Option<usize> {
+        self.materials.iter().position(|m| m.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn movement(&self, p_type: usize) -> Option<Movement> {
+        self.get(p_type).map(|m| m.movement)
+    }
+
+    /// Loose materials that pile up (and that magnets can grab).
+    pub fn is_grain(&self, p_type: usize) -> bool {
+        self.movement(p_type) == Some(Movement::Powder)
+    }
+
+    /// Add `material`, replacing any existing one with the same name (which
+    /// keeps its index). Returns its particle type, or `None` if the
+    /// registry is full.
+    pub fn define(&mut self, material: Material) -> Option<usize> {
+        match self.by_name(&material.name) {
+            Some(p_type) => {
+                self.materials[p_type] = material;
+                Some(p_type)
+            }
+            None if self.materials.len() < MAX_MATERIALS => {
+                self.materials.push(material);
+                Some(self.materials.len() - 1)
+            }
+            None => None,
+        }
+    }
+
+    /// Read material definitions from text, one per line:
+    ///
+    /// ```text
+    /// # name  color    density  movement  flammability  [fall_speed [fall_interval [trail]]]
+    /// lava    #ff6010  3.0      liquid    0.0           1            2              #ff2000
+    /// ```
+    ///
+    /// Colors are `#rrggbb`, `#rrggbbaa` or `none`. Movement is one of
+    /// `powder`, `liquid`, `solid` or `gas`. Fall speed and interval default
+    /// to 1. The trail color defaults to `DAMP` for liquids, the usual hot
+    /// orange for anything else that moves, and none for solids. Blank lines
+    /// and `#` comments are skipped (a `#` followed by a hex digit starts a
+    /// color, not a comment). Redefining a material by name replaces it.
+    pub fn load_str(&mut self, text: &str) -> Result<(), ParseError> {
+        for (n, line) in text.lines().enumerate() {
+            let line_no = n + 1;
+            let fields: Vec<&str> = line
+                .split_whitespace()
+                .take_while(|f| !f.starts_with('#') || parse_color(f).is_some())
+                .collect();
+            if fields.is_empty() {
+                continue;
+            }
+            if !(5..=8).contains(&fields.len()) {
+                return Err(ParseError::new(
+                    line_no,
+                    format!("expected 5 to 8 fields, found {}", fields.len()),
+                ));
+            }
+            let number = |field: &str, what: &str| {
+                field
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|x| x.is_finite() && *x >= 0.0)
+                    .ok_or_else(|| ParseError::new(line_no, format!("bad {} {:?}", what, field)))
+            };
+            let color_field = |field: &str| match field {
+                none if none.eq_ignore_ascii_case("none") => Ok(None),
+                hex => parse_color(hex)
+                    .map(Some)
+                    .ok_or_else(|| ParseError::new(line_no, format!("bad color {:?}", hex))),
+            };
+            let color = color_field(fields[1])?;
+            let movement = Movement::parse(fields[3]).ok_or_else(|| {
+                ParseError::new(line_no, format!("unknown movement {:?}", fields[3]))
+            })?;
+            let flammability = number(fields[4], "flammability")?;
+            if flammability > 1.0 {
+                return Err(ParseError::new(line_no, "flammability must be in 0..=1"));
+            }
+            let fall_speed = match fields.get(5) {
+                Some(f) => f
+                    .parse()
+                    .map_err(|_| ParseError::new(line_no, format!("bad fall speed {:?}", f)))?,
+                None => 1,
+            };
+            let fall_interval = match fields.get(6) {
+                Some(f) => f
+                    .parse()
+                    .ok()
+                    .filter(|&i| i > 0)
+                    .ok_or_else(|| ParseError::new(line_no, format!("bad fall interval {:?}", f)))?,
+                None => 1,
+            };
+            let trail = match fields.get(7) {
+                Some(f) => color_field(f)?,
+                None => default_trail(movement),
+            };
+            let material = Material {
+                name: fields[0].to_lowercase(),
+                color,
+                density: number(fields[2], "density")?,
+                movement,
+                flammability,
+                gravity: Gravity {
+                    fall_speed,
+                    fall_interval,
+                },
+                dissipation: 0.0,
+                trail,
+            };
+            if self.define(material).is_none() {
+                return Err(ParseError::new(line_no, "too many materials"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `#rrggbb` or `#rrggbbaa`.
+fn parse_color(text: &str) -> Option<[u8; 4]> {
+    let hex = text.strip_prefix('#')?;
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    let alpha = if hex.len() == 8 { byte(6)? } else { 0xff };
+    Some([byte(0)?, byte(2)?, byte(4)?, alpha])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Movement, Particle, SandGrid, DAMP, HOT, MAGNET, SAND, WATER};
+
+    #[test]
+    fn trails_follow_each_material() {
+        let mut grid = SandGrid::new_empty(5, 5);
+        grid.materials_mut().load_str("ash #808080 0.8 powder 0.0 1 1 none").unwrap();
+        let ash = grid.materials().by_name("ash").unwrap();
+        assert_eq!(grid.materials().movement(WATER), Some(Movement::Liquid));
+        grid.set(0, 0, Particle::new(WATER, true));
+        grid.set(2, 0, Particle::new(ash, true));
+        grid.set(4, 0, Particle::new(SAND, true));
+        grid.set(4, 2, Particle::new(MAGNET, true));
+        grid.set_show_heat(true);
+        grid.update();
+        let pixel = |x: usize, y: usize| {
+            let i = 4 * (x + y * 5);
+            grid.render_to_vec()[i..i + 4].to_vec()
+        };
+        // Each left its own trail behind, or none at all.
+        assert_eq!(pixel(0, 0), DAMP);
+        assert_eq!(pixel(2, 0), [0, 0, 0, 0xff]);
+        assert_eq!(pixel(4, 0), HOT);
+        assert_eq!(grid.materials().get(MAGNET).unwrap().trail, None);
+    }
+
+    #[test]
+    fn custom_materials_move_by_their_movement() {
+        let mut grid = SandGrid::new_empty(9, 5);
+        grid.reseed((1, 2));
+        grid.materials_mut()
+            .load_str(
+                "rock #606060 3.0 solid 0.0\ngrit #c0a060 1.5 powder 0.0\n\
+                 oil #302010 0.9 liquid 0.0\nvapor #ffffff80 0.01 gas 0.0",
+            )
+            .unwrap();
+        let kind = |name: &str| grid.materials().by_name(name).unwrap();
+        let (rock, grit, oil, vapor) = (kind("rock"), kind("grit"), kind("oil"), kind("vapor"));
+        for x in 0..8 {
+            grid.set(x, 4, Particle::new(rock, false));
+        }
+        grid.set(0, 0, Particle::new(rock, true));
+        grid.set(2, 1, Particle::new(grit, true));
+        grid.set(4, 3, Particle::new(vapor, true));
+        grid.set(6, 3, Particle::new(oil, true));
+        grid.update();
+        // Rock stays put even in mid-air, grit falls, and oil flows along
+        // the rock towards the gap where grit would come to rest.
+        assert_eq!(grid.pick(0, 0), Some(rock));
+        assert_eq!(grid.pick(2, 2), Some(grit));
+        assert_eq!(grid.pick(7, 3), Some(oil));
+        for _ in 0..10 {
+            grid.update();
+        }
+        // Vapor wanders a little, but ends up at the top.
+        assert!((0..9).any(|x| grid.pick(x, 0) == Some(vapor)));
+        assert_eq!(grid.pick(2, 3), Some(grit));
+        assert_eq!(grid.pick(8, 4), Some(oil));
+    }
+}
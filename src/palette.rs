@@ -0,0 +1,109 @@
+use crate::{MaterialRegistry, ParseError, NONE};
+
+/// The materials the user may draw with, in the order the number keys pick
+/// them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    materials: Vec<usize>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl Palette {
+    /// Every built-in drawable material.
+    pub fn all() -> Self {
+        Self::all_of(&MaterialRegistry::builtin())
+    }
+
+    /// Every drawable material in `registry`.
+    pub fn all_of(registry: &MaterialRegistry) -> Self {
+        Self {
+            materials: (0..registry.len()).filter(|&p| p != NONE).collect(),
+        }
+    }
+
+    /// A palette of exactly `materials`, in that order. Empty or unknown
+    /// entries are dropped, and an empty list means `all`.
+    pub fn new(materials: &[usize]) -> Self {
+        Self::new_of(materials, &MaterialRegistry::builtin())
+    }
+
+    /// Same as `new`, for the materials in `registry`.
+    pub fn new_of(materials: &[usize], registry: &MaterialRegistry) -> Self {
+        let all = Self::all_of(registry);
+        let mut palette = Self { materials: Vec::new() };
+        for &p_type in materials {
+            if all.allows(p_type) && !palette.allows(p_type) {
+                palette.materials.push(p_type);
+            }
+        }
+        if palette.materials.is_empty() {
+            return all;
+        }
+        palette
+    }
+
+    /// Parse a comma separated list of built-in material names, e.g.
+    /// `sand,magnet`.
+    pub fn parse(list: &str) -> Result<Self, ParseError> {
+        Self::parse_with(list, &MaterialRegistry::builtin())
+    }
+
+    /// Same as `parse`, looking names up in `registry`.
+    pub fn parse_with(list: &str, registry: &MaterialRegistry) -> Result<Self, ParseError> {
+        let mut materials = Vec::new();
+        for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match registry.by_name(name) {
+                Some(p_type) if p_type != NONE => materials.push(p_type),
+                _ => return Err(ParseError::new(1, format!("unknown material {:?}", name))),
+            }
+        }
+        Ok(Self::new_of(&materials, registry))
+    }
+
+    pub fn materials(&self) -> &[usize] {
+        &self.materials
+    }
+
+    pub fn allows(&self, p_type: usize) -> bool {
+        self.materials.contains(&p_type)
+    }
+
+    /// The material in (0-based) `slot`, if the palette has that many.
+    pub fn get(&self, slot: usize) -> Option<usize> {
+        self.materials.get(slot).copied()
+    }
+
+    /// The allowed material after `current`, wrapping around. Starts from
+    /// the first if `current` isn't allowed.
+    pub fn next(&self, current: usize) -> usize {
+        match self.materials.iter().position(|&p| p == current) {
+            Some(i) => self.materials[(i + 1) % self.materials.len()],
+            None => self.materials[0],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DUST, MAGNET, SAND, SMOKE};
+
+    #[test]
+    fn cycling_skips_disallowed_materials() {
+        let palette = Palette::parse("smoke, sand,dust").unwrap();
+        assert_eq!(palette.materials(), &[SMOKE, SAND, DUST]);
+        assert_eq!(palette.next(SMOKE), SAND);
+        assert_eq!(palette.next(SAND), DUST);
+        assert_eq!(palette.next(DUST), SMOKE);
+        // Coming from something not on the palette starts it over.
+        assert_eq!(palette.next(MAGNET), SMOKE);
+        assert!(!palette.allows(MAGNET));
+        assert!(Palette::parse("sand,lava").is_err());
+        assert_eq!(Palette::new(&[NONE]), Palette::all());
+    }
+}
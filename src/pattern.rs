@@ -0,0 +1,256 @@
+use std::fmt;
+
+/// A pattern of live cells, e.g. a Life glider, independent of any grid.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pattern {
+    pub width: usize,
+    pub height: usize,
+    /// `(x, y)` of each live cell, relative to the pattern's top-left.
+    pub cells: Vec<(usize, usize)>,
+}
+
+/// Why a pattern (or other text input) couldn't be parsed. `line` is 1-based.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new<S: Into<String>>(line: usize, message: S) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatternFormat {
+    /// One row per line, `.` for dead cells and `O`/`*`/`#` for live ones.
+    Ascii,
+    /// The run-length encoded format most Life software uses.
+    Rle,
+    /// Plaintext `.cells`: `.` and `O` rows under `!` comment lines.
+    Cells,
+}
+
+/// Guess the format: `.cells` if it starts with a `!` comment, RLE if the
+/// first non-comment line is an `x = ...` header, ASCII otherwise.
+pub fn detect_format(text: &str) -> PatternFormat {
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    if lines.clone().next().is_some_and(|line| line.starts_with('!')) {
+        return PatternFormat::Cells;
+    }
+    let header = lines.find(|line| !line.starts_with('#'));
+    match header {
+        Some(line) if line.starts_with('x') && line[1..].trim_start().starts_with('=') => {
+            PatternFormat::Rle
+        }
+        _ => PatternFormat::Ascii,
+    }
+}
+
+/// Parse `text` in whichever format `detect_format` picks.
+pub fn parse_pattern(text: &str) -> Result<Pattern, ParseError> {
+    match detect_format(text) {
+        PatternFormat::Ascii => from_ascii(text),
+        PatternFormat::Rle => from_rle(text),
+        PatternFormat::Cells => from_cells(text),
+    }
+}
+
+impl Pattern {
+    fn push(&mut self, x: usize, y: usize) {
+        self.cells.push((x, y));
+        self.width = self.width.max(x + 1);
+        self.height = self.height.max(y + 1);
+    }
+}
+
+pub fn from_ascii(text: &str) -> Result<Pattern, ParseError> {
+    let mut pattern = Pattern::default();
+    for (y, line) in text.lines().enumerate() {
+        let line = line.trim_end();
+        for (x, c) in line.chars().enumerate() {
+            match c {
+                'O' | 'o' | '*' | '#' | 'X' | 'x' | '@' => pattern.push(x, y),
+                '.' | ' ' | '-' | '_' => {}
+                other => return Err(ParseError::new(y + 1, format!("unexpected {:?}", other))),
+            }
+        }
+        pattern.height = pattern.height.max(y + 1);
+        pattern.width = pattern.width.max(line.chars().count());
+    }
+    Ok(pattern)
+}
+
+/// Parse the plaintext `.cells` format: `!` lines are comments, and each
+/// other line is a row of `.` (dead) and `O` (live) cells. Short rows are
+/// padded out with dead cells.
+pub fn from_cells(text: &str) -> Result<Pattern, ParseError> {
+    let mut pattern = Pattern::default();
+    let mut y = 0;
+    for (n, line) in text.lines().enumerate() {
+        if line.starts_with('!') {
+            continue;
+        }
+        let line = line.trim_end();
+        for (x, c) in line.chars().enumerate() {
+            match c {
+                'O' | 'o' | '*' => pattern.push(x, y),
+                '.' => {}
+                other => return Err(ParseError::new(n + 1, format!("unexpected {:?}", other))),
+            }
+        }
+        pattern.width = pattern.width.max(line.chars().count());
+        y += 1;
+        pattern.height = pattern.height.max(y);
+    }
+    Ok(pattern)
+}
+
+/// Widest (and tallest) RLE pattern `from_rle` accepts, so a bad header
+/// can't make it build something enormous.
+pub const MAX_RLE_SIDE: usize = 1 << 12;
+
+/// Parse the run-length encoded format. Runs have to stay within the size
+/// the header declares.
+pub fn from_rle(text: &str) -> Result<Pattern, ParseError> {
+    let mut pattern = Pattern::default();
+    let mut header = None;
+    let (mut x, mut y) = (0, 0);
+    let mut count: Option<usize> = None;
+    'lines: for (n, line) in text.lines().enumerate() {
+        let line_no = n + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (width, height) = match header {
+            Some(size) => size,
+            None => {
+                let (width, height) = parse_rle_header(line).ok_or_else(|| {
+                    ParseError::new(line_no, "expected an `x = <width>, y = <height>` header")
+                })?;
+                if width.max(height) > MAX_RLE_SIDE {
+                    let message = format!("{}x{} is bigger than {} cells across", width, height, MAX_RLE_SIDE);
+                    return Err(ParseError::new(line_no, message));
+                }
+                header = Some((width, height));
+                continue;
+            }
+        };
+        // Where a run of `count` (default 1) from `at` ends, if that's
+        // within `limit`.
+        let run_end = |at: usize, count: Option<usize>, limit: usize, what: &str| {
+            at.checked_add(count.unwrap_or(1))
+                .filter(|&end| end <= limit)
+                .ok_or_else(|| ParseError::new(line_no, format!("run goes past the pattern's {}", what)))
+        };
+        for c in line.chars() {
+            match c {
+                '0'..='9' => {
+                    let digit = c as usize - '0' as usize;
+                    count = Some(
+                        count
+                            .unwrap_or(0)
+                            .checked_mul(10)
+                            .and_then(|n| n.checked_add(digit))
+                            .ok_or_else(|| ParseError::new(line_no, "run length too long"))?,
+                    );
+                }
+                'b' => x = run_end(x, count.take(), width, "width")?,
+                '$' => {
+                    y = run_end(y, count.take(), height, "height")?;
+                    x = 0;
+                }
+                '!' => break 'lines,
+                c if c.is_whitespace() => {}
+                c if c.is_ascii_alphabetic() => {
+                    let end = run_end(x, count.take(), width, "width")?;
+                    run_end(y, None, height, "height")?;
+                    for x in x..end {
+                        pattern.push(x, y);
+                    }
+                    x = end;
+                }
+                other => {
+                    return Err(ParseError::new(line_no, format!("unexpected {:?}", other)));
+                }
+            }
+        }
+    }
+    let (width, height) = header.ok_or_else(|| ParseError::new(1, "missing header"))?;
+    pattern.width = pattern.width.max(width);
+    pattern.height = pattern.height.max(height);
+    Ok(pattern)
+}
+
+/// `x = 3, y = 3, rule = B3/S23` => `(3, 3)`.
+fn parse_rle_header(line: &str) -> Option<(usize, usize)> {
+    let (mut width, mut height) = (None, None);
+    for field in line.split(',') {
+        let mut kv = field.splitn(2, '=');
+        let key = kv.next()?.trim();
+        let value = kv.next()?.trim();
+        match key {
+            "x" => width = value.parse().ok(),
+            "y" => height = value.parse().ok(),
+            _ => {}
+        }
+    }
+    Some((width?, height?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_ascii_and_rle() {
+        assert_eq!(detect_format(".O.\n..O\nOOO"), PatternFormat::Ascii);
+        assert_eq!(detect_format("#C comment\n\nx = 3, y = 3\nbo$2bo$3o!"), PatternFormat::Rle);
+        assert_eq!(detect_format("x=3,y=3\nbo$2bo$3o!"), PatternFormat::Rle);
+        // Not RLE just because a row starts with an x.
+        assert_eq!(detect_format("xx.\n.xx"), PatternFormat::Ascii);
+        let glider = parse_pattern(".O.\n..O\nOOO").unwrap();
+        assert_eq!(parse_pattern("x = 3, y = 3\nbo$2bo$3o!").unwrap(), glider);
+    }
+
+    #[test]
+    fn malformed_patterns_are_errors() {
+        // An RLE header with a body that isn't RLE.
+        assert_eq!(detect_format("x = 3, y = 3\n.O.\n..O"), PatternFormat::Rle);
+        assert_eq!(parse_pattern("x = 3, y = 3\n.O.\n..O").unwrap_err().line, 2);
+        // Anything else is taken for ASCII, and fails as that.
+        assert_eq!(detect_format("hello\nworld"), PatternFormat::Ascii);
+        assert_eq!(parse_pattern(".O.\nhello").unwrap_err().line, 2);
+        assert_eq!(detect_format(""), PatternFormat::Ascii);
+        assert!(parse_pattern("").unwrap().cells.is_empty());
+    }
+
+    #[test]
+    fn glider_parses() {
+        let glider = from_rle("#N Glider\nx = 3, y = 3, rule = B3/S23\nbo$2bo$3o!").unwrap();
+        assert_eq!(glider, from_cells(".O.\n..O\nOOO").unwrap());
+    }
+
+    #[test]
+    fn runs_stay_within_the_header() {
+        assert!(from_rle("x = 3, y = 3\n999999999999999999999o!").is_err());
+        assert!(from_rle("x = 3, y = 3\n18446744073709551615b2o!").is_err());
+        assert!(from_rle("x = 3, y = 3\n18446744073709551615$o!").is_err());
+        assert!(from_rle("x = 3, y = 3\n4o!").is_err());
+        assert!(from_rle("x = 3, y = 3\n3$o!").is_err());
+        assert!(from_rle("x = 100000, y = 1\no!").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use crate::{MaterialRegistry, Movement, ParseError};
+
+/// When a cell of `a` is orthogonally next to a cell of `b`, with chance
+/// `probability` per update they turn into `result_a` and `result_b`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reaction {
+    pub a: usize,
+    pub b: usize,
+    pub result_a: usize,
+    pub result_b: usize,
+    pub probability: f32,
+    /// Only react when `a` is directly above `b`.
+    pub above: bool,
+}
+
+/// The reactions `update` applies between neighbouring materials.
+///
+/// A grid starts with the `builtin` table. Draining into holes, being
+/// pulled by magnets and smoke fading stay part of how materials move.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReactionTable {
+    reactions: Vec<Reaction>,
+}
+
+impl Default for ReactionTable {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl ReactionTable {
+    /// An empty table.
+    pub fn new() -> Self {
+        Self { reactions: Vec::new() }
+    }
+
+    /// The interactions between the built-in materials.
+    pub fn builtin() -> Self {
+        Self::for_materials(&MaterialRegistry::builtin())
+    }
+
+    /// Anything loose sinks through a lighter liquid or gas right below it:
+    /// `a` above `b` trades places with it every update.
+    pub fn for_materials(materials: &MaterialRegistry) -> Self {
+        let mut table = Self::new();
+        for (a, upper) in materials.iter().enumerate() {
+            for (b, lower) in materials.iter().enumerate() {
+                if upper.movement != Movement::Solid
+                    && matches!(lower.movement, Movement::Liquid | Movement::Gas)
+                    && lower.density < upper.density
+                {
+                    table.set(Reaction {
+                        a,
+                        b,
+                        result_a: b,
+                        result_b: a,
+                        probability: 1.0,
+                        above: true,
+                    });
+                }
+            }
+        }
+        table
+    }
+
+    /// Add a reaction, replacing any existing one for the same `(a, b)` pair.
+    pub fn set(&mut self, reaction: Reaction) {
+        self.remove(reaction.a, reaction.b);
+        self.reactions.push(reaction);
+    }
+
+    pub fn remove(&mut self, a: usize, b: usize) {
+        self.reactions.retain(|r| (r.a, r.b) != (a, b));
+    }
+
+    pub fn get(&self, a: usize, b: usize) -> Option<&Reaction> {
+        self.reactions.iter().find(|r| (r.a, r.b) == (a, b))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reactions.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Reaction> {
+        self.reactions.iter()
+    }
+
+    /// Read reactions from text, one per line:
+    ///
+    /// ```text
+    /// # a    b     result_a  result_b  probability
+    /// sand   water dust      water     0.05
+    /// dust   water water     dust      0.5    above
+    /// ```
+    ///
+    /// A trailing `above` only lets the reaction happen with `a` directly
+    /// above `b`. Blank lines and `#` comments are skipped. Entries override
+    /// whatever is already in the table. Only built-in materials can be
+    /// named; see `load_str_with` for custom ones.
+    pub fn load_str(&mut self, text: &str) -> Result<(), ParseError> {
+        self.load_str_with(text, &MaterialRegistry::builtin())
+    }
+
+    /// Same as `load_str`, looking material names up in `materials`.
+    pub fn load_str_with(&mut self, text: &str, materials: &MaterialRegistry) -> Result<(), ParseError> {
+        for (n, line) in text.lines().enumerate() {
+            let line_no = n + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if !(5..=6).contains(&fields.len()) {
+                return Err(ParseError::new(
+                    line_no,
+                    format!("expected 5 or 6 fields, found {}", fields.len()),
+                ));
+            }
+            let material = |name: &str| {
+                materials
+                    .by_name(name)
+                    .ok_or_else(|| ParseError::new(line_no, format!("unknown material {:?}", name)))
+            };
+            let probability: f32 = fields[4]
+                .parse()
+                .ok()
+                .filter(|p| (0.0..=1.0).contains(p))
+                .ok_or_else(|| ParseError::new(line_no, "probability must be in 0..=1"))?;
+            let above = match fields.get(5) {
+                None => false,
+                Some(f) if f.eq_ignore_ascii_case("above") => true,
+                Some(f) => return Err(ParseError::new(line_no, format!("expected \"above\", found {:?}", f))),
+            };
+            self.set(Reaction {
+                a: material(fields[0])?,
+                b: material(fields[1])?,
+                result_a: material(fields[2])?,
+                result_b: material(fields[3])?,
+                probability,
+                above,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Particle, SandGrid, DUST, HOLE, MAGNET, SAND, SMOKE, WATER};
+
+    #[test]
+    fn custom_reaction_fires_at_its_rate() {
+        let mut reactions = ReactionTable::new();
+        reactions.load_str("magnet hole dust hole 0.25").unwrap();
+        let mut grid = SandGrid::new_empty(2, 1);
+        grid.reseed((1, 2));
+        *grid.reactions_mut() = reactions;
+        let mut fired = 0;
+        for _ in 0..4000 {
+            grid.set(0, 0, Particle::new(MAGNET, false));
+            grid.set(1, 0, Particle::new(HOLE, false));
+            grid.update();
+            if grid.pick(0, 0) == Some(DUST) {
+                assert_eq!(grid.pick(1, 0), Some(HOLE));
+                fired += 1;
+            }
+        }
+        assert!((900..1100).contains(&fired), "fired {} of 4000", fired);
+    }
+
+    #[test]
+    fn later_entries_override() {
+        let mut reactions = ReactionTable::new();
+        reactions.load_str("sand water dust water 0.05\nsand water sand sand 0.5").unwrap();
+        assert_eq!(reactions.iter().count(), 1);
+        assert_eq!(reactions.get(SAND, WATER).unwrap().probability, 0.5);
+        assert!(reactions.load_str("sand water dust water 2").is_err());
+        reactions.load_str("sand water water sand 1 above").unwrap();
+        assert!(reactions.get(SAND, WATER).unwrap().above);
+        assert!(reactions.load_str("sand water water sand 1 below").is_err());
+    }
+
+    #[test]
+    fn builtin_table_sinks_grains_through_lighter_fluids() {
+        let table = ReactionTable::builtin();
+        let sink = table.get(SAND, SMOKE).unwrap();
+        assert_eq!((sink.result_a, sink.result_b, sink.above), (SMOKE, SAND, true));
+        assert!(table.get(SMOKE, SAND).is_none());
+        assert!(table.get(SAND, DUST).is_none());
+        assert_eq!(SandGrid::new_empty(1, 1).reactions(), &table);
+
+        let mut grid = SandGrid::new_empty(1, 2);
+        grid.set(0, 0, Particle::new(SAND, false));
+        grid.set(0, 1, Particle::new(SMOKE, false));
+        grid.update();
+        assert_eq!(grid.pick(0, 1), Some(SAND));
+        // Side by side, nothing sinks.
+        let mut grid = SandGrid::new_empty(2, 1);
+        grid.set(0, 0, Particle::new(SAND, false));
+        grid.set(1, 0, Particle::new(SMOKE, false));
+        grid.update();
+        assert_eq!(grid.pick(0, 0), Some(SAND));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{Input, SandGrid, DEFAULT_WARMUP_UPDATES};
+
+/// How `AutoRefill` livens up a grid that's gone still.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefillMode {
+    /// Scatter fresh noise, as with `randomize`.
+    Randomize,
+    /// Pour a row of the brush material along the top.
+    Rain,
+}
+
+impl RefillMode {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.trim() {
+            "randomize" => Some(RefillMode::Randomize),
+            "rain" => Some(RefillMode::Rain),
+            _ => None,
+        }
+    }
+}
+
+/// Watches for the grid going still (everything drained away or settled)
+/// and refills it, so an unattended display never sits blank.
+#[derive(Clone, Debug)]
+pub struct AutoRefill {
+    pub mode: RefillMode,
+    /// Updates in a row with nothing moving before refilling.
+    pub after: u64,
+    still: u64,
+}
+
+impl AutoRefill {
+    pub fn new(mode: RefillMode, after: u64) -> Self {
+        Self { mode, after, still: 0 }
+    }
+
+    /// Call after each update. Once nothing has moved for `after` updates
+    /// in a row, returns the edit that refills `grid`, and starts counting
+    /// again.
+    pub fn check(&mut self, grid: &SandGrid) -> Option<Input> {
+        if grid.active_count() > 0 {
+            self.still = 0;
+            return None;
+        }
+        self.still += 1;
+        if self.still < self.after {
+            return None;
+        }
+        self.still = 0;
+        Some(match self.mode {
+            RefillMode::Randomize => Input::Randomize { warmup: DEFAULT_WARMUP_UPDATES },
+            RefillMode::Rain => {
+                let top = grid.margin() as isize;
+                let right = grid.width() as isize - 1;
+                Input::Emit { x0: 0, y0: top, x1: right, y1: top, radius: 0 }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Particle, SAND};
+
+    fn still_grid() -> SandGrid {
+        let mut grid = SandGrid::new_empty(20, 20);
+        grid.reseed((1, 2));
+        grid
+    }
+
+    #[test]
+    fn refills_once_still_for_long_enough() {
+        let mut grid = still_grid();
+        let mut refill = AutoRefill::new(RefillMode::Randomize, 3);
+        assert_eq!(refill.check(&grid), None);
+        assert_eq!(refill.check(&grid), None);
+        let input = refill.check(&grid).expect("no refill");
+        assert!(matches!(input, Input::Randomize { .. }));
+        input.apply(&mut grid);
+        // The fresh noise settles too, and gets refilled again.
+        grid.settle();
+        let mut fired = false;
+        for _ in 0..3 {
+            fired = refill.check(&grid).is_some();
+        }
+        assert!(fired);
+    }
+
+    #[test]
+    fn movement_resets_the_timer() {
+        let mut grid = still_grid();
+        let mut refill = AutoRefill::new(RefillMode::Rain, 3);
+        assert_eq!(refill.check(&grid), None);
+        assert_eq!(refill.check(&grid), None);
+        grid.set(5, 0, Particle::new(SAND, true));
+        grid.update();
+        assert_eq!(refill.check(&grid), None);
+        grid.settle();
+        assert_eq!(refill.check(&grid), None);
+        assert_eq!(refill.check(&grid), None);
+        assert!(matches!(refill.check(&grid), Some(Input::Emit { y0: 0, .. })));
+    }
+}
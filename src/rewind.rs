@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+
+use crate::SandGrid;
+
+/// The last `depth` frames of a grid, for stepping back through recent
+/// history frame by frame. Memory is bounded by `depth` copies of the grid.
+///
+/// Unlike a recorded `Session` nothing has to be replayed: every frame is a
+/// full snapshot.
+pub struct Rewind {
+    depth: usize,
+    frames: VecDeque<SandGrid>,
+    // How many frames back from the newest we're currently looking at.
+    back: usize,
+}
+
+impl Rewind {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth: depth.max(1),
+            frames: VecDeque::new(),
+            back: 0,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Frames currently held.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// How many frames back from the newest the last `back`/`forward` went.
+    pub fn position(&self) -> usize {
+        self.back
+    }
+
+    /// Snapshot `grid` as the newest frame, dropping the oldest past `depth`.
+    /// If we'd rewound, the frames after the one rewound to are forgotten
+    /// first, since `grid` carries on from there.
+    pub fn push(&mut self, grid: &SandGrid) {
+        let keep = self.frames.len() - self.back;
+        self.frames.truncate(keep);
+        self.back = 0;
+        if self.frames.len() == self.depth {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(grid.clone());
+    }
+
+    /// Step `steps` frames further back (as far as the oldest held) and
+    /// return that frame.
+    pub fn back(&mut self, steps: usize) -> Option<&SandGrid> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        self.back = (self.back + steps).min(self.frames.len() - 1);
+        self.current()
+    }
+
+    /// Step `steps` frames towards the newest and return that frame.
+    pub fn forward(&mut self, steps: usize) -> Option<&SandGrid> {
+        self.back = self.back.saturating_sub(steps);
+        self.current()
+    }
+
+    fn current(&self) -> Option<&SandGrid> {
+        let len = self.frames.len();
+        self.frames.get(len.checked_sub(self.back + 1)?)
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.back = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn back_k_frames_restores_that_frame() {
+        let mut grid = SandGrid::new_empty(30, 30);
+        grid.reseed((1, 2));
+        grid.randomize();
+        let mut rewind = Rewind::new(10);
+        let mut hashes = Vec::new();
+        for _ in 0..8 {
+            rewind.push(&grid);
+            hashes.push(grid.state_hash());
+            grid.update();
+        }
+        for k in 0..8 {
+            rewind.forward(8);
+            let frame = rewind.back(k).unwrap();
+            assert_eq!(frame.state_hash(), hashes[7 - k]);
+            assert_eq!(rewind.position(), k);
+        }
+        // Never further back than the oldest frame held.
+        assert_eq!(rewind.back(100).unwrap().state_hash(), hashes[0]);
+    }
+
+    #[test]
+    fn keeps_only_depth_frames() {
+        let mut grid = SandGrid::new_empty(10, 10);
+        let mut rewind = Rewind::new(3);
+        for _ in 0..5 {
+            rewind.push(&grid);
+            grid.update();
+        }
+        assert_eq!(rewind.len(), 3);
+    }
+
+    #[test]
+    fn pushing_after_rewinding_drops_the_newer_frames() {
+        let mut grid = SandGrid::new_empty(10, 10);
+        grid.randomize();
+        let mut rewind = Rewind::new(10);
+        for _ in 0..4 {
+            rewind.push(&grid);
+            grid.update();
+        }
+        let old = rewind.back(2).unwrap().clone();
+        rewind.push(&old);
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.position(), 0);
+    }
+}
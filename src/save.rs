@@ -0,0 +1,220 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{Particle, SandGrid};
+
+const MAGIC: &[u8; 4] = b"GOSS";
+
+/// One record per cell.
+pub const SAVE_VERSION_FLAT: u8 = 1;
+/// Runs of identical cells, much smaller for mostly empty grids.
+pub const SAVE_VERSION_RLE: u8 = 2;
+/// Version written by `write_save` and `save_to_path`.
+pub const SAVE_VERSION: u8 = SAVE_VERSION_RLE;
+
+/// Largest grid `read_save` accepts, in cells (and along either side), so a
+/// corrupt header can't ask for an absurd allocation.
+const MAX_LOAD_CELLS: usize = 1 << 24;
+const MAX_LOAD_SIDE: usize = 1 << 15;
+
+fn invalid<S: Into<String>>(message: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn truncated(e: io::Error) -> io::Error {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        invalid("save is cut short")
+    } else {
+        e
+    }
+}
+
+impl SandGrid {
+    /// Save the grid's cells. Settings (brush, cohesion, hooks and so on)
+    /// aren't included.
+    ///
+    /// Layout, little endian: `GOSS`, version byte, width and height as
+    /// `u32`, then the cells row by row. Each cell is its type and whether
+    /// it's active, a byte each. Version 1 stores every cell like that;
+    /// version 2 stores runs of identical cells, each as a `u32` count
+    /// followed by the cell.
+    pub fn write_save<W: Write>(&self, out: W) -> io::Result<()> {
+        self.write_save_as(out, SAVE_VERSION)
+    }
+
+    /// Same as `write_save`, in a given `version` of the format (say
+    /// `SAVE_VERSION_FLAT`, for older builds to read).
+    pub fn write_save_as<W: Write>(&self, mut out: W, version: u8) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_u8(version)?;
+        out.write_u32::<LittleEndian>(self.width as u32)?;
+        out.write_u32::<LittleEndian>(self.height as u32)?;
+        let cell = |p: &Particle| (p.p_type as u8, p.active as u8);
+        match version {
+            SAVE_VERSION_FLAT => {
+                for p in &self.particles {
+                    let (p_type, active) = cell(p);
+                    out.write_u8(p_type)?;
+                    out.write_u8(active)?;
+                }
+            }
+            SAVE_VERSION_RLE => {
+                let mut cells = self.particles.iter().map(cell).peekable();
+                while let Some(run) = cells.next() {
+                    let mut count: u32 = 1;
+                    while count < u32::MAX && cells.peek() == Some(&run) {
+                        cells.next();
+                        count += 1;
+                    }
+                    out.write_u32::<LittleEndian>(count)?;
+                    out.write_u8(run.0)?;
+                    out.write_u8(run.1)?;
+                }
+            }
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown save version {}", other),
+                ))
+            }
+        }
+        out.flush()
+    }
+
+    /// Read a grid written by `write_save`, in either version. Anything
+    /// malformed, cut short or implausibly big fails with `InvalidData`.
+    pub fn read_save<R: Read>(mut input: R) -> io::Result<SandGrid> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a saved grid"));
+        }
+        let version = input.read_u8()?;
+        if version != SAVE_VERSION_FLAT && version != SAVE_VERSION_RLE {
+            return Err(invalid(format!("unsupported save version {}", version)));
+        }
+        let width = input.read_u32::<LittleEndian>()? as usize;
+        let height = input.read_u32::<LittleEndian>()? as usize;
+        let size = width.saturating_mul(height);
+        if width == 0 || height == 0 || width.max(height) > MAX_LOAD_SIDE || size > MAX_LOAD_CELLS {
+            return Err(invalid(format!("bad grid size {}x{}", width, height)));
+        }
+        // Read every run before making the grid, so a truncated file fails
+        // without allocating whatever size the header claims.
+        let mut runs: Vec<(usize, (u8, u8))> = Vec::new();
+        let mut idx = 0;
+        while idx < size {
+            let count = match version {
+                SAVE_VERSION_RLE => input.read_u32::<LittleEndian>().map_err(truncated)? as usize,
+                _ => 1,
+            };
+            if count == 0 || count > size - idx {
+                return Err(invalid(format!("bad run of {} cells at cell {}", count, idx)));
+            }
+            let cell = (input.read_u8().map_err(truncated)?, input.read_u8().map_err(truncated)?);
+            match runs.last_mut() {
+                Some((n, last)) if *last == cell => *n += count,
+                _ => runs.push((count, cell)),
+            }
+            idx += count;
+        }
+        let mut grid = SandGrid::new_empty(width, height);
+        let mut idx = 0;
+        for (count, (p_type, active)) in runs {
+            // Types past the built-ins are custom materials, which have to be
+            // registered again after loading.
+            let p = Particle::new(p_type as usize, active != 0);
+            for cell in &mut grid.particles[idx..idx + count] {
+                *cell = p;
+            }
+            idx += count;
+        }
+        Ok(grid)
+    }
+
+    /// Save to `path`, going through a temporary file so a failed save
+    /// doesn't clobber an existing one.
+    pub fn save_to_path<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.save_to_path_as(path, SAVE_VERSION)
+    }
+
+    /// Same as `save_to_path`, in a given `version` of the format.
+    pub fn save_to_path_as<P: AsRef<Path>>(&self, path: P, version: u8) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        self.write_save_as(BufWriter::new(File::create(&tmp)?), version)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn load_from_path<P: AsRef<Path>>(path: P) -> io::Result<SandGrid> {
+        SandGrid::read_save(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved(grid: &SandGrid, version: u8) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        grid.write_save_as(&mut bytes, version).unwrap();
+        bytes
+    }
+
+    fn header(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(SAVE_VERSION_FLAT);
+        bytes.write_u32::<LittleEndian>(width).unwrap();
+        bytes.write_u32::<LittleEndian>(height).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn rle_round_trips_and_is_smaller() {
+        let mut grid = SandGrid::new_empty(100, 80);
+        grid.reseed((1, 2));
+        grid.randomize_region(10, 10, 20, 20, 0.5, false);
+        let rle = saved(&grid, SAVE_VERSION_RLE);
+        let flat = saved(&grid, SAVE_VERSION_FLAT);
+        for bytes in &[&rle, &flat] {
+            let loaded = SandGrid::read_save(&bytes[..]).unwrap();
+            assert_eq!(loaded.state_hash(), grid.state_hash());
+        }
+        assert!(rle.len() * 5 < flat.len(), "{} vs {} bytes", rle.len(), flat.len());
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let mut grid = SandGrid::new_empty(30, 20);
+        grid.randomize();
+        let path = std::env::temp_dir().join(format!("conways_gos_test_{}.gos", std::process::id()));
+        grid.save_to_path(&path).unwrap();
+        let loaded = SandGrid::load_from_path(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.unwrap().state_hash(), grid.state_hash());
+    }
+
+    #[test]
+    fn truncated_save_is_invalid() {
+        let mut grid = SandGrid::new_empty(20, 10);
+        grid.randomize();
+        for version in &[SAVE_VERSION_FLAT, SAVE_VERSION_RLE] {
+            let bytes = saved(&grid, *version);
+            for len in &[bytes.len() - 1, bytes.len() / 2, 13] {
+                let err = SandGrid::read_save(&bytes[..*len]).err().expect("loaded a truncated save");
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_header_is_invalid() {
+        for &(width, height) in &[(u32::MAX, u32::MAX), (1 << 20, 1), (1 << 14, 1 << 14), (0, 5)] {
+            let err = SandGrid::read_save(&header(width, height)[..]).err().expect("loaded a huge grid");
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
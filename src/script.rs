@@ -0,0 +1,298 @@
+use crate::{
+    save_screenshot, Gravity, Input, MaterialRegistry, ParseError, SandGrid, ScreenshotScale,
+    DEFAULT_WARMUP_UPDATES,
+};
+use std::io;
+
+/// Something a script does to the grid.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// An edit, just as the front end would make it.
+    Input(Input),
+    /// Change how a material falls.
+    Gravity { p_type: usize, gravity: Gravity },
+    Seed(u64, u64),
+    /// Save a one-pixel-per-cell PNG.
+    Screenshot(String),
+}
+
+impl Command {
+    /// Apply the command. Only screenshots can fail.
+    pub fn apply(&self, grid: &mut SandGrid) -> io::Result<()> {
+        match self {
+            Command::Input(input) => {
+                input.apply(grid);
+            }
+            Command::Gravity { p_type, gravity } => {
+                if let Some(material) = grid.materials_mut().get_mut(*p_type) {
+                    material.gravity = *gravity;
+                }
+            }
+            Command::Seed(a, b) => grid.reseed((*a, *b)),
+            Command::Screenshot(path) => save_screenshot(grid, path, ScreenshotScale::Native)?,
+        }
+        Ok(())
+    }
+}
+
+/// Commands to run at set frames, for reproducible demos. A frame is counted
+/// each time the grid advances, so commands at frame 0 run before anything
+/// moves.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Script {
+    // Sorted by frame, in file order within a frame.
+    commands: Vec<(u64, Command)>,
+    next: usize,
+}
+
+impl Script {
+    /// Read a script, one command per line, prefixed with the frame to run
+    /// it at:
+    ///
+    /// ```text
+    /// 0    seed 1 2
+    /// 0    randomize 20          # warm-up updates, optional
+    /// 10   brush magnet
+    /// 10   line 0 100 200 120
+    /// 30   place sand 100 10     # also leaves sand as the brush
+    /// 60   gravity sand 1 2      # fall speed and (optional) interval
+    /// 60   fill 0.5
+    /// 90   clear
+    /// 120  screenshot demo.png
+    /// ```
+    ///
+    /// Blank lines and `#` comments are skipped. Material names are looked
+    /// up in `materials`.
+    pub fn parse(text: &str, materials: &MaterialRegistry) -> Result<Self, ParseError> {
+        let mut commands = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line_no = n + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let frame: u64 = fields[0]
+                .parse()
+                .map_err(|_| ParseError::new(line_no, format!("bad frame {:?}", fields[0])))?;
+            let (name, args) = match fields.get(1) {
+                Some(&name) => (name, &fields[2..]),
+                None => return Err(ParseError::new(line_no, "missing command")),
+            };
+            let arity = |range: std::ops::RangeInclusive<usize>| {
+                let expected = if range.start() == range.end() {
+                    range.start().to_string()
+                } else {
+                    format!("{} to {}", range.start(), range.end())
+                };
+                if range.contains(&args.len()) {
+                    Ok(())
+                } else {
+                    Err(ParseError::new(
+                        line_no,
+                        format!("{} takes {} arguments, found {}", name, expected, args.len()),
+                    ))
+                }
+            };
+            let number = |i: usize| {
+                args[i]
+                    .parse::<isize>()
+                    .map_err(|_| ParseError::new(line_no, format!("bad number {:?}", args[i])))
+            };
+            let material = |i: usize| {
+                materials
+                    .by_name(args[i])
+                    .ok_or_else(|| ParseError::new(line_no, format!("unknown material {:?}", args[i])))
+            };
+            let mut push = |command| commands.push((frame, command));
+            match name {
+                "line" => {
+                    arity(4..=4)?;
+                    let (x0, y0, x1, y1) = (number(0)?, number(1)?, number(2)?, number(3)?);
+                    push(Command::Input(Input::Line { x0, y0, x1, y1, active: true }));
+                }
+                "place" => {
+                    arity(3..=3)?;
+                    let (x, y) = (number(1)?, number(2)?);
+                    push(Command::Input(Input::Brush(material(0)?)));
+                    push(Command::Input(Input::Line { x0: x, y0: y, x1: x, y1: y, active: true }));
+                }
+                "brush" => {
+                    arity(1..=1)?;
+                    push(Command::Input(Input::Brush(material(0)?)));
+                }
+                "randomize" => {
+                    arity(0..=1)?;
+                    let warmup = match args.first() {
+                        Some(_) => number(0)?.max(0) as usize,
+                        None => DEFAULT_WARMUP_UPDATES,
+                    };
+                    push(Command::Input(Input::Randomize { warmup }));
+                }
+                "fill" => {
+                    arity(1..=1)?;
+                    let density = args[0]
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|d| (0.0..=1.0).contains(d))
+                        .ok_or_else(|| ParseError::new(line_no, format!("bad fill {:?}", args[0])))?;
+                    push(Command::Input(Input::FillDensity(density)));
+                }
+                "clear" => {
+                    arity(0..=0)?;
+                    push(Command::Input(Input::Clear));
+                }
+                "gravity" => {
+                    arity(2..=3)?;
+                    let p_type = material(0)?;
+                    let fall_speed = number(1)?.max(0) as usize;
+                    let fall_interval = match args.get(2) {
+                        Some(f) => f
+                            .parse()
+                            .ok()
+                            .filter(|&i| i > 0)
+                            .ok_or_else(|| ParseError::new(line_no, format!("bad fall interval {:?}", f)))?,
+                        None => 1,
+                    };
+                    push(Command::Gravity { p_type, gravity: Gravity { fall_speed, fall_interval } });
+                }
+                "seed" => {
+                    arity(2..=2)?;
+                    let seed = |i: usize| {
+                        args[i]
+                            .parse::<u64>()
+                            .map_err(|_| ParseError::new(line_no, format!("bad seed {:?}", args[i])))
+                    };
+                    push(Command::Seed(seed(0)?, seed(1)?));
+                }
+                "screenshot" => {
+                    arity(1..=1)?;
+                    push(Command::Screenshot(args[0].to_string()));
+                }
+                other => return Err(ParseError::new(line_no, format!("unknown command {:?}", other))),
+            }
+        }
+        commands.sort_by_key(|&(frame, _)| frame);
+        Ok(Self { commands, next: 0 })
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Whether every command has been handed out by `due`.
+    pub fn is_finished(&self) -> bool {
+        self.next == self.commands.len()
+    }
+
+    /// The commands to run at `frame`, along with any earlier ones not yet
+    /// handed out. Each command is only returned once.
+    pub fn due(&mut self, frame: u64) -> Vec<Command> {
+        let start = self.next;
+        while self.next < self.commands.len() && self.commands[self.next].0 <= frame {
+            self.next += 1;
+        }
+        self.commands[start..self.next].iter().map(|(_, c)| c.clone()).collect()
+    }
+
+    /// Apply whatever is `due` at `frame` to `grid`, logging (and skipping)
+    /// commands that fail.
+    pub fn run(&mut self, frame: u64, grid: &mut SandGrid) {
+        for command in self.due(frame) {
+            if let Err(e) = command.apply(grid) {
+                log::error!("Script command {:?} failed: {}", command, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MAGNET, SAND, SMOKE};
+
+    fn parse(text: &str) -> Result<Script, ParseError> {
+        Script::parse(text, &MaterialRegistry::builtin())
+    }
+
+    #[test]
+    fn parses_each_command() {
+        let mut script = parse(
+            "# demo\n\
+             10 line 0 1 2 3\n\
+             0  seed 1 2\n\
+             0  randomize\n\
+             1  randomize 20   # longer warm-up\n\
+             2  brush magnet\n\
+             3  place sand 4 5\n\
+             4  fill 0.5\n\
+             5  gravity sand 3\n\
+             5  gravity smoke 1 2\n\
+             6  clear\n\
+             7  screenshot out.png\n",
+        )
+        .unwrap();
+        let line = |x0, y0, x1, y1| Command::Input(Input::Line { x0, y0, x1, y1, active: true });
+        let gravity = |p_type, fall_speed, fall_interval| Command::Gravity {
+            p_type,
+            gravity: Gravity { fall_speed, fall_interval },
+        };
+        assert_eq!(
+            script.due(u64::MAX),
+            vec![
+                Command::Seed(1, 2),
+                Command::Input(Input::Randomize { warmup: DEFAULT_WARMUP_UPDATES }),
+                Command::Input(Input::Randomize { warmup: 20 }),
+                Command::Input(Input::Brush(MAGNET)),
+                Command::Input(Input::Brush(SAND)),
+                line(4, 5, 4, 5),
+                Command::Input(Input::FillDensity(0.5)),
+                gravity(SAND, 3, 1),
+                gravity(SMOKE, 1, 2),
+                Command::Input(Input::Clear),
+                Command::Screenshot("out.png".to_string()),
+                line(0, 1, 2, 3),
+            ]
+        );
+        assert!(script.is_finished());
+    }
+
+    #[test]
+    fn errors_point_at_their_line() {
+        let cases = [
+            ("0 seed 1 2\n\n5 explode", 3, "unknown command"),
+            ("x clear", 1, "bad frame"),
+            ("0 clear\n3", 2, "missing command"),
+            ("0 line 1 2 3", 1, "line takes 4 arguments, found 3"),
+            ("0 randomize 1 2", 1, "randomize takes 0 to 1 arguments, found 2"),
+            ("0 place lava 1 1", 1, "unknown material"),
+            ("0 line 0 0 a 0", 1, "bad number"),
+            ("0 clear\n0 fill 1.5", 2, "bad fill"),
+            ("0 gravity sand 1 0", 1, "bad fall interval"),
+            ("0 seed -1 2", 1, "bad seed"),
+        ];
+        for &(text, line, message) in cases.iter() {
+            let e = parse(text).unwrap_err();
+            assert_eq!(e.line, line, "{:?}", text);
+            assert!(e.message.starts_with(message), "{:?} gave {:?}", text, e.message);
+        }
+    }
+
+    #[test]
+    fn running_a_script_edits_the_grid() {
+        let mut script = parse("0 brush sand\n0 line 0 0 3 0\n2 place magnet 2 4").unwrap();
+        let mut grid = SandGrid::new_empty(5, 5);
+        script.run(0, &mut grid);
+        let row: Vec<_> = (0..5).map(|x| grid.pick(x, 0)).collect();
+        assert_eq!(row, [Some(SAND), Some(SAND), Some(SAND), Some(SAND), None]);
+        script.run(1, &mut grid);
+        assert_eq!(grid.pick(2, 4), None);
+        script.run(2, &mut grid);
+        assert_eq!(grid.pick(2, 4), Some(MAGNET));
+        assert!(script.is_finished());
+    }
+}
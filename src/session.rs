@@ -0,0 +1,162 @@
+use crate::{Pattern, SandGrid};
+
+/// An edit made to a `SandGrid` from the front end. Recording these (plus
+/// when updates happened) is enough to replay a session, since everything
+/// random comes from the grid's own seeded PRNG.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Input {
+    Toggle { x: isize, y: isize },
+    Nudge { x: isize, y: isize, dx: isize, dy: isize },
+    Line { x0: isize, y0: isize, x1: isize, y1: isize, active: bool },
+    Emit { x0: isize, y0: isize, x1: isize, y1: isize, radius: usize },
+    Brush(usize),
+    RainbowEmit(bool),
+    Cohesion(f32),
+    FillDensity(f32),
+    Clear,
+    Flip { horizontal: bool },
+    Randomize { warmup: usize },
+    RandomizeRegion { x0: isize, y0: isize, x1: isize, y1: isize, fill: f32, keep_walls: bool },
+    Pattern { pattern: Pattern, x: isize, y: isize },
+}
+
+impl Input {
+    /// Apply the edit. Returns what `toggle` would for `Toggle` and `nudge`
+    /// for `Nudge`, otherwise `false`.
+    pub fn apply(&self, grid: &mut SandGrid) -> bool {
+        match *self {
+            Input::Toggle { x, y } => return grid.toggle(x, y),
+            Input::Nudge { x, y, dx, dy } => return grid.nudge(x, y, dx, dy),
+            Input::Line { x0, y0, x1, y1, active } => grid.set_line(x0, y0, x1, y1, active),
+            Input::Emit { x0, y0, x1, y1, radius } => {
+                grid.emit_line(x0, y0, x1, y1, radius);
+            }
+            Input::Brush(p_type) => grid.set_brush_type(p_type),
+            Input::RainbowEmit(rainbow) => grid.set_rainbow_emit(rainbow),
+            Input::Cohesion(cohesion) => grid.set_cohesion(cohesion),
+            Input::FillDensity(density) => grid.set_fill_density(density),
+            Input::Clear => grid.clear(),
+            Input::Flip { horizontal: true } => grid.flip_horizontal(),
+            Input::Flip { horizontal: false } => grid.flip_vertical(),
+            Input::Randomize { warmup } => grid.randomize_steps(warmup),
+            Input::RandomizeRegion { x0, y0, x1, y1, fill, keep_walls } => {
+                grid.randomize_region(x0, y0, x1, y1, fill, keep_walls)
+            }
+            Input::Pattern { ref pattern, x, y } => grid.place_pattern(pattern, x, y),
+        }
+        false
+    }
+}
+
+/// Advance `grid` by one frame the way the front end does: pending randomize
+/// warm-up takes priority over a normal update.
+pub fn advance(grid: &mut SandGrid) {
+    if grid.warmup_progress().is_some() {
+        grid.warmup_step();
+    } else {
+        grid.update();
+    }
+}
+
+/// A recorded session: the inputs made at each frame, plus a snapshot of the
+/// grid every `interval` frames so seeking doesn't have to replay from the
+/// start.
+///
+/// A "frame" here is one `advance`; frame `n` is the state after `n` of them,
+/// before any input made during frame `n`.
+pub struct Session {
+    interval: u64,
+    frames: u64,
+    inputs: Vec<(u64, Input)>,
+    keyframes: Vec<(u64, SandGrid)>,
+}
+
+impl Session {
+    pub fn start(grid: &SandGrid, interval: u64) -> Self {
+        assert!(interval > 0);
+        Self {
+            interval,
+            frames: 0,
+            inputs: Vec::new(),
+            keyframes: vec![(0, grid.clone())],
+        }
+    }
+
+    /// Record an input made during the current frame.
+    pub fn record(&mut self, input: Input) {
+        self.inputs.push((self.frames, input));
+    }
+
+    /// Note that `grid` was just advanced a frame.
+    pub fn step(&mut self, grid: &SandGrid) {
+        self.frames += 1;
+        if self.frames % self.interval == 0 {
+            self.keyframes.push((self.frames, grid.clone()));
+        }
+    }
+
+    /// How many frames have been recorded.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Rebuild the grid as it was at `frame` (clamped to what was recorded),
+    /// starting from the closest keyframe at or before it.
+    pub fn seek(&self, frame: u64) -> SandGrid {
+        let frame = frame.min(self.frames);
+        let (start, keyframe) = self
+            .keyframes
+            .iter()
+            .rev()
+            .find(|(f, _)| *f <= frame)
+            .expect("keyframe 0 is always present");
+        let mut grid = keyframe.clone();
+        let first = self.inputs.partition_point(|(f, _)| f < start);
+        let mut inputs = self.inputs[first..].iter().peekable();
+        for f in *start..frame {
+            while let Some((_, input)) = inputs.next_if(|(at, _)| *at == f) {
+                input.apply(&mut grid);
+            }
+            advance(&mut grid);
+        }
+        grid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WATER;
+
+    #[test]
+    fn seek_matches_playing_straight_through() {
+        let mut grid = SandGrid::new_empty(40, 30);
+        grid.reseed((1, 2));
+        grid.randomize();
+        let mut session = Session::start(&grid, 7);
+        // Hash at each frame, before that frame's inputs.
+        let mut hashes = Vec::new();
+        for frame in 0..50 {
+            hashes.push(grid.state_hash());
+            let inputs = match frame {
+                3 => vec![Input::Toggle { x: 5, y: 5 }],
+                10 => vec![Input::Emit { x0: 0, y0: 0, x1: 39, y1: 0, radius: 1 }, Input::Brush(WATER)],
+                20 => vec![Input::Emit { x0: 20, y0: 2, x1: 20, y1: 2, radius: 2 }],
+                33 => vec![Input::Randomize { warmup: 2 }],
+                _ => vec![],
+            };
+            for input in inputs {
+                session.record(input.clone());
+                input.apply(&mut grid);
+            }
+            advance(&mut grid);
+            session.step(&grid);
+        }
+        hashes.push(grid.state_hash());
+        for frame in 0..=50 {
+            assert_eq!(session.seek(frame).state_hash(), hashes[frame as usize], "frame {}", frame);
+        }
+        // Past the end clamps to the last frame.
+        assert_eq!(session.seek(1000).state_hash(), hashes[50]);
+    }
+}
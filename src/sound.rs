@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+
+use crate::Move;
+
+/// Works out how many grains landed from consecutive `Move` lists (as passed
+/// to the update hook): a grain landed if it moved last update but didn't
+/// move on from where it ended up.
+#[derive(Debug, Default)]
+pub struct ImpactDetector {
+    last_destinations: HashSet<(usize, usize)>,
+}
+
+impl ImpactDetector {
+    pub fn landed(&mut self, moves: &[Move]) -> usize {
+        let still_moving = moves
+            .iter()
+            .filter(|m| self.last_destinations.contains(&m.from))
+            .count();
+        let landed = self.last_destinations.len() - still_moving;
+        self.last_destinations = moves.iter().map(|m| m.to).collect();
+        landed
+    }
+}
+
+/// Decides when an impact is worth a sound: at least `threshold` grains
+/// landing at once, and no more often than every `min_interval` seconds.
+#[derive(Debug)]
+pub struct ImpactTrigger {
+    pub threshold: usize,
+    pub min_interval: f64,
+    last_fired: Option<f64>,
+}
+
+impl ImpactTrigger {
+    pub fn new(threshold: usize, min_interval: f64) -> Self {
+        Self {
+            threshold,
+            min_interval,
+            last_fired: None,
+        }
+    }
+
+    /// Whether `landed` grains at time `now` (seconds) should play a sound.
+    pub fn observe(&mut self, landed: usize, now: f64) -> bool {
+        let rested = self.last_fired.map_or(true, |t| now - t >= self.min_interval);
+        let fire = landed >= self.threshold && rested;
+        if fire {
+            self.last_fired = Some(now);
+        }
+        fire
+    }
+}
+
+/// Plays short clicks on the default audio output.
+#[cfg(feature = "sound")]
+pub struct ClickPlayer {
+    // Dropping the stream stops all audio, so hold on to it.
+    _stream: rodio::OutputStream,
+    handle: rodio::OutputStreamHandle,
+}
+
+#[cfg(feature = "sound")]
+impl ClickPlayer {
+    pub fn new() -> Result<Self, rodio::StreamError> {
+        let (stream, handle) = rodio::OutputStream::try_default()?;
+        Ok(Self {
+            _stream: stream,
+            handle,
+        })
+    }
+
+    /// A short blip, pitched up the more grains landed.
+    pub fn click(&self, landed: usize) {
+        use rodio::Source;
+        use std::time::Duration;
+
+        let pitch = 440.0 + 20.0 * landed.min(40) as f32;
+        let click = rodio::source::SineWave::new(pitch)
+            .take_duration(Duration::from_millis(30))
+            .amplify(0.2);
+        if let Err(e) = self.handle.play_raw(click.convert_samples()) {
+            log::warn!("Couldn't play click: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_impacts_stay_quiet() {
+        let mut trigger = ImpactTrigger::new(5, 0.1);
+        assert!(!trigger.observe(4, 0.0));
+        assert!(!trigger.observe(0, 1.0));
+        assert!(trigger.observe(5, 2.0));
+    }
+
+    #[test]
+    fn one_click_per_interval() {
+        let mut trigger = ImpactTrigger::new(5, 0.1);
+        assert!(trigger.observe(20, 1.0));
+        assert!(!trigger.observe(20, 1.05));
+        assert!(trigger.observe(20, 1.1));
+    }
+
+    #[test]
+    fn grains_land_when_they_stop_moving() {
+        let fall = |from, to| Move { from, to, p_type: crate::SAND };
+        let mut detector = ImpactDetector::default();
+        assert_eq!(detector.landed(&[fall((0, 0), (0, 1)), fall((2, 0), (2, 1))]), 0);
+        assert_eq!(detector.landed(&[fall((0, 1), (0, 2))]), 1);
+        assert_eq!(detector.landed(&[]), 1);
+    }
+}
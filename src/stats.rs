@@ -0,0 +1,85 @@
+use std::fmt::Write;
+
+use crate::SandGrid;
+
+/// A snapshot of the grid's statistics after one update, as written out by
+/// `--stats-out`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepStats {
+    pub step: u64,
+    pub particles: usize,
+    /// Particles still in motion (see `SandGrid::active_count`).
+    pub active: usize,
+    pub center_of_mass: Option<(f32, f32)>,
+    /// `(x, height)` of the tallest column.
+    pub tallest_column: Option<(usize, usize)>,
+    /// Grains that crossed the flow row this update.
+    pub flow_crossings: u32,
+    /// Grains per second through the flow row, from a `FlowRate`.
+    pub flow_rate: f64,
+}
+
+impl StepStats {
+    pub fn collect(grid: &SandGrid, step: u64, flow_rate: f64) -> Self {
+        Self {
+            step,
+            particles: grid.particle_count(),
+            active: grid.active_count(),
+            center_of_mass: grid.center_of_mass(),
+            tallest_column: grid.tallest_column(),
+            flow_crossings: grid.flow_crossings(),
+            flow_rate,
+        }
+    }
+}
+
+/// Render `stats` as a JSON array with one object per step.
+pub fn stats_json(stats: &[StepStats]) -> String {
+    let mut json = String::from("[");
+    for (i, s) in stats.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let center = match s.center_of_mass {
+            Some((x, y)) => format!("[{}, {}]", x, y),
+            None => "null".to_string(),
+        };
+        let tallest = match s.tallest_column {
+            Some((x, height)) => format!("{{\"x\": {}, \"height\": {}}}", x, height),
+            None => "null".to_string(),
+        };
+        // Writing to a String can't fail.
+        let _ = write!(
+            json,
+            "\n  {{\"step\": {}, \"particles\": {}, \"active\": {}, \"center_of_mass\": {}, \
+             \"tallest_column\": {}, \"flow_crossings\": {}, \"flow_rate\": {}}}",
+            s.step, s.particles, s.active, center, tallest, s.flow_crossings, s.flow_rate
+        );
+    }
+    json.push_str("\n]\n");
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_has_an_entry_per_step() {
+        let mut grid = SandGrid::new_empty(20, 20);
+        grid.reseed((1, 2));
+        grid.randomize();
+        let stats: Vec<StepStats> = (1..=4)
+            .map(|step| {
+                grid.update();
+                StepStats::collect(&grid, step, 0.0)
+            })
+            .collect();
+        let json = stats_json(&stats);
+        assert_eq!(json.matches("\"step\": ").count(), 4);
+        for field in &["particles", "active", "center_of_mass", "tallest_column", "flow_crossings", "flow_rate"] {
+            assert_eq!(json.matches(&format!("\"{}\": ", field)).count(), 4, "{}", field);
+        }
+        assert!(json.trim_start().starts_with('[') && json.trim_end().ends_with(']'));
+    }
+}
@@ -0,0 +1,87 @@
+/// Picks how many updates to run per frame so that simulating stays under a
+/// frame-time budget, based on a moving average of what one update costs.
+#[derive(Clone, Debug)]
+pub struct StepTuner {
+    /// Seconds per frame we're allowed to spend updating.
+    pub target: f64,
+    /// Only step up when the extra update would still leave this fraction of
+    /// the budget unused, so we don't oscillate around the target.
+    pub headroom: f64,
+    pub min_steps: usize,
+    pub max_steps: usize,
+    /// Weight of the newest sample in the moving average.
+    pub smoothing: f64,
+    average: Option<f64>,
+    steps: usize,
+}
+
+impl StepTuner {
+    pub fn new(target: f64) -> Self {
+        Self {
+            target,
+            headroom: 0.2,
+            min_steps: 1,
+            max_steps: 64,
+            smoothing: 0.2,
+            average: None,
+            steps: 1,
+        }
+    }
+
+    /// Feed the measured cost in seconds of one update, returning the new
+    /// steps per frame. Moves at most one step per call.
+    pub fn record(&mut self, seconds_per_update: f64) -> usize {
+        let average = match self.average {
+            Some(avg) => avg + (seconds_per_update - avg) * self.smoothing,
+            None => seconds_per_update,
+        };
+        self.average = Some(average);
+        if average * self.steps as f64 > self.target {
+            self.steps = self.steps.saturating_sub(1).max(self.min_steps);
+        } else if average * (self.steps + 1) as f64 <= self.target * (1.0 - self.headroom) {
+            self.steps = (self.steps + 1).min(self.max_steps);
+        }
+        self.steps
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Average cost of one update so far, in seconds.
+    pub fn average(&self) -> Option<f64> {
+        self.average
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settles_on_what_fits_the_budget() {
+        let mut tuner = StepTuner::new(0.016);
+        for _ in 0..100 {
+            tuner.record(0.001);
+        }
+        // 12 updates use 12ms, leaving the 20% headroom of a 16ms budget.
+        assert_eq!(tuner.steps(), 12);
+        let mut last = tuner.steps();
+        for _ in 0..100 {
+            let steps = tuner.record(0.004);
+            assert!(steps <= last && last - steps <= 1);
+            last = steps;
+        }
+        assert_eq!(tuner.steps(), 4);
+    }
+
+    #[test]
+    fn can_back_off_to_zero_steps() {
+        let mut tuner = StepTuner::new(0.016);
+        tuner.min_steps = 0;
+        for _ in 0..10 {
+            tuner.record(1.0);
+        }
+        assert_eq!(tuner.steps(), 0);
+    }
+}